@@ -1,5 +1,12 @@
 use scrypto::prelude::*;
 
+#[derive(TypeId, Encode, Decode, Describe, PartialEq)]
+pub enum LoanPolicy {
+    Exact,          // lend exactly the requested amount or fail
+    UpTo,           // lend the requested amount or as much as is available
+    MaxAvailable,   // lend everything above the liquidity buffer and the bank's cut
+}
+
 blueprint! {
     struct Bank {
         loan_interest: Decimal,
@@ -8,6 +15,13 @@ blueprint! {
         lender_accounts: LazyMap<Address, (Vault, ResourceDef)>,
         lender_lookup: LazyMap<Address, Address>, // <LenderTokenAddr, TokenAddr>
         reserves: LazyMap<Address, Vault>,
+        outstanding_loans: LazyMap<Address, Decimal>, // <TokenAddr, amount currently flash loaned out>
+        liquidity_buffer: Decimal, // percentage of each pool that is never lent out
+        rate_history: LazyMap<(Address, u64), (u64, Decimal)>, // <(TokenAddr, index), (epoch, underlying tokens per lender token)>
+        snapshot_counts: LazyMap<Address, (u64, u64)>, // <TokenAddr, (snapshots taken, epoch of the last one)>
+        epochs_per_year: u64,
+        successors: LazyMap<Address, (Address, Vault)>, // <TokenAddr, (successorBank, successor lender tokens)>
+        fee_policy: Option<Address>, // component with a loan_fee(currency, amount, borrower) method
         admin_badge: ResourceDef,
     }
 
@@ -24,6 +38,13 @@ blueprint! {
                 lender_accounts: LazyMap::new(),
                 lender_lookup: LazyMap::new(),
                 reserves: LazyMap::new(),
+                outstanding_loans: LazyMap::new(),
+                liquidity_buffer: dec!(0),
+                rate_history: LazyMap::new(),
+                snapshot_counts: LazyMap::new(),
+                epochs_per_year: 8760, // assumes hourly epochs until set by the admin
+                successors: LazyMap::new(),
+                fee_policy: None,
                 admin_badge: admin_badge.resource_def(),
             }
             .instantiate();
@@ -34,10 +55,15 @@ blueprint! {
         // mints new lender tokens at the current exchange rate
         pub fn deposit(&mut self, payment: Bucket) -> Bucket {
             let address = payment.resource_address();
+            self.snapshot_rate(address);
+            if self.successors.get(&address).is_some() {
+                return self.deposit_to_successor(address, payment);
+            }
             match self.lender_accounts.get(&address){
                 Some(acc) =>{
                     let (mut vault, mut resource) = acc;
-                    let exchange_rate: Decimal = if resource.total_supply() > dec!(0) { resource.total_supply()/vault.amount() } else { dec!(1) };
+                    let backing: Decimal = vault.amount() + self.outstanding(address);
+                    let exchange_rate: Decimal = if resource.total_supply() > dec!(0) { resource.total_supply()/backing } else { dec!(1) };
                     let lenders_bought: Decimal = exchange_rate*payment.amount();
                     vault.put(payment);
                     self.lender_badge.authorize(|auth|{
@@ -61,6 +87,7 @@ blueprint! {
                     });
                     self.lender_accounts.insert(address, (v,lender_resource_def));
                     self.lender_lookup.insert(t.resource_address(),address);
+                    self.snapshot_rate(address);
                     return t;
                 }
             }
@@ -72,10 +99,18 @@ blueprint! {
                 Some(addr) => {addr}
                 None => {panic!("Invalid lender token")}
             };
+            self.snapshot_rate(address);
+            // lenders that never migrated redeem through the successor
+            if let Some((successor, _successor_tokens)) = self.successors.get(&address) {
+                let successor_lenders = self.migrate(lenders);
+                return Component::from(successor).call::<Bucket>("withdraw", vec![scrypto_encode(&successor_lenders)]).into();
+            }
             match self.lender_accounts.get(&address){
                 Some(acc) =>{
                     let (mut vault, mut resource) = acc;
-                    let cash_returned: Decimal = (vault.amount()/resource.total_supply())*lenders.amount();
+                    let backing: Decimal = vault.amount() + self.outstanding(address);
+                    let cash_returned: Decimal = (backing/resource.total_supply())*lenders.amount();
+                    assert!(cash_returned <= vault.amount(), "Not enough liquidity to withdraw while funds are loaned out");
                     self.lender_badge.authorize(|auth|{
                         resource.burn_with_auth(lenders, auth);
                     });
@@ -90,78 +125,319 @@ blueprint! {
         #[auth(admin_badge)]
         pub fn withdraw_bank_cut(&mut self, currency: Address) -> Bucket{
             let (_vault, resource_def) = self.lender_accounts.get(&currency).unwrap();
-            self.withdraw(self.reserves.get(&resource_def.address()).unwrap().take_all())
+            let reserve_fund = self.reserves.get(&resource_def.address()).unwrap().take_all();
+            if self.successors.get(&currency).is_some() {
+                self.migrate(reserve_fund)
+            }else{
+                self.withdraw(reserve_fund)
+            }
         }
 
-        // modified flash loan code from tweeted repo
+        // moves a pool's liquidity into a successor bank. Deposits and withdrawals keep working through the
+        // successor afterwards, so components holding lender tokens don't have to migrate them
+        #[auth(admin_badge)]
+        pub fn migrate_pool(&mut self, currency: Address, successor: Address) {
+            self.assert_not_migrated(currency);
+            assert!(self.outstanding(currency) == dec!(0), "Cannot migrate while funds are loaned out");
+            let (mut vault, _resource) = match self.lender_accounts.get(&currency){
+                Some(acc) => acc,
+                None => panic!("No lender account found")
+            };
+            self.snapshot_rate(currency);
+
+            let args = vec![
+                scrypto_encode(&vault.take_all())
+            ];
+            let successor_tokens: Bucket = Component::from(successor).call::<Bucket>("deposit", args).into();
+            self.successors.insert(currency, (successor, Vault::with_bucket(successor_tokens)));
+        }
+
+        // swaps lender tokens of a migrated pool for their pro-rata share of the successor's lender tokens.
+        // that is 1:1 only when the successor prices its lender tokens like this pool did, a pro-rata share
+        // keeps the swap fair when it doesn't, e.g. a successor that already has lenders at another rate
+        pub fn migrate(&mut self, lenders: Bucket) -> Bucket {
+            let address = match self.lender_lookup.get(&lenders.resource_address()){
+                Some(addr) => {addr}
+                None => {panic!("Invalid lender token")}
+            };
+            let (_successor, mut successor_tokens) = match self.successors.get(&address){
+                Some(s) => s,
+                None => panic!("This pool has not been migrated")
+            };
+            let (_vault, mut resource) = self.lender_accounts.get(&address).unwrap();
+            let successor_share: Decimal = successor_tokens.amount()*lenders.amount()/resource.total_supply();
+            self.lender_badge.authorize(|auth|{
+                resource.burn_with_auth(lenders, auth);
+            });
+            successor_tokens.take(successor_share)
+        }
+
+        // deposits into the successor and mints lender tokens of this pool for the successor tokens received
+        fn deposit_to_successor(&mut self, currency: Address, payment: Bucket) -> Bucket {
+            let (successor, mut successor_tokens) = self.successors.get(&currency).unwrap();
+            let (_vault, mut resource) = self.lender_accounts.get(&currency).unwrap();
+            let received: Bucket = Component::from(successor).call::<Bucket>("deposit", vec![scrypto_encode(&payment)]).into();
+            let lenders_bought: Decimal = if resource.total_supply() > dec!(0) && successor_tokens.amount() > dec!(0) {
+                resource.total_supply()*received.amount()/successor_tokens.amount()
+            } else {
+                received.amount()
+            };
+            successor_tokens.put(received);
+            self.lender_badge.authorize(|auth|{
+                resource.mint(lenders_bought, auth)
+            })
+        }
+
+        pub fn get_successor(&self, currency: Address) -> Option<Address> {
+            match self.successors.get(&currency){
+                Some((successor, _successor_tokens)) => Some(successor),
+                None => None
+            }
+        }
+
+        fn assert_not_migrated(&self, currency: Address) {
+            match self.successors.get(&currency){
+                Some((successor, _successor_tokens)) => panic!("This pool has already migrated to {}", successor),
+                None => {}
+            }
+        }
+
+        // lends exactly the requested amount or fails
         pub fn request_loan(&mut self, amount: Decimal, currency: Address, component_address: Address) -> Bucket {
-            match self.lender_accounts.get(&currency){
-                Some(acc) =>{
-                    let (mut vault, _resource) = acc;
+            self.borrow(LoanPolicy::Exact, amount, currency, component_address)
+        }
 
-                    assert!(amount <= vault.amount(), "Not enough funds to loan");
+        // lends out all the cash in the vault above the liquidity buffer and the bank's cut
+        pub fn request_max_loan(&mut self, currency: Address, component_address: Address) -> Bucket {
+            self.borrow(LoanPolicy::MaxAvailable, dec!(0), currency, component_address)
+        }
 
-                    // Call the execute method at the specified component's address with the requested funds
-                    let args = vec![
-                        scrypto_encode(&vault.take(amount))
-                    ];
+        // similar to request_loan but will loan max rather than fail if the amount cannot be filled
+        pub fn request_loan_upto(&mut self, amount: Decimal, currency: Address, component_address: Address) -> Bucket {
+            self.borrow(LoanPolicy::UpTo, amount, currency, component_address)
+        }
+
+        // flash loans funds according to the given policy, the amount is ignored for MaxAvailable
+        pub fn borrow(&mut self, policy: LoanPolicy, amount: Decimal, currency: Address, component_address: Address) -> Bucket {
+            self.snapshot_rate(currency);
+            let available = self.available_to_lend(currency);
+            let loan_amount = match policy {
+                LoanPolicy::Exact => {
+                    assert!(self.lender_accounts.get(&currency).is_some(), "No liquidity for this token is available");
+                    assert!(amount <= available, "Not enough funds to loan");
+                    amount
+                }
+                LoanPolicy::UpTo => if amount > available {available} else {amount},
+                LoanPolicy::MaxAvailable => available,
+            };
 
-                    let mut returned_bucket: Bucket = Component::from(component_address).call::<Bucket>("execute", args).into();
+            // nothing to lend, so don't call the borrower with an empty bucket
+            if loan_amount == dec!(0) {
+                return Bucket::new(currency);
+            }
 
-                    // Make sure they repaid in loan in full
-                    let amount_to_take = amount * ((self.loan_interest / 100) + 1);
-                    assert!(returned_bucket.amount() >= amount_to_take, "You have to return more than {}", amount_to_take);
+            self.lend(loan_amount, currency, component_address)
+        }
 
+        #[auth(admin_badge)]
+        pub fn set_liquidity_buffer(&mut self, liquidity_buffer: Decimal) {
+            assert!(liquidity_buffer >= dec!(0) && liquidity_buffer <= dec!(100), "The liquidity buffer must be a percentage between 0 and 100");
+            self.liquidity_buffer = liquidity_buffer;
+        }
 
-                    let mut repayment = returned_bucket.take(amount_to_take);
-                    let reserve_base_tokens = repayment.take( (amount_to_take - amount)*(self.bank_cut/100) );
-                    vault.put(repayment);
-                    let reserve_fund = self.deposit(reserve_base_tokens);
+        // modified flash loan code from tweeted repo
+        fn lend(&mut self, amount: Decimal, currency: Address, component_address: Address) -> Bucket {
+            let (mut vault, _resource) = self.lender_accounts.get(&currency).unwrap();
 
-                    let token_addr = reserve_fund.resource_address();
-                    match self.reserves.get(&token_addr) {
-                        Some(mut v) => {
-                            v.put(reserve_fund);
-                        }
-                        None => {
-                            let v = Vault::with_bucket(reserve_fund);
-                            self.reserves.insert(token_addr, v);
-                        }
-                    };
+            // Call the execute method at the specified component's address with the requested funds
+            let args = vec![
+                scrypto_encode(&vault.take(amount))
+            ];
+
+            // lent out funds still back the lender tokens while the borrower holds them
+            let outstanding = self.outstanding(currency);
+            self.outstanding_loans.insert(currency, outstanding + amount);
+
+            let mut returned_bucket: Bucket = Component::from(component_address).call::<Bucket>("execute", args).into();
+
+            // Make sure they repaid in loan in full
+            let amount_to_take = amount + self.loan_fee(currency, amount, component_address);
+            assert!(returned_bucket.amount() >= amount_to_take, "You have to return more than {}", amount_to_take);
+
+
+            let mut repayment = returned_bucket.take(amount_to_take);
+            let reserve_base_tokens = repayment.take( (amount_to_take - amount)*(self.bank_cut/100) );
+            vault.put(repayment);
+            self.outstanding_loans.insert(currency, outstanding);
+            let reserve_fund = self.deposit(reserve_base_tokens);
 
-                    // Return the change back to the component
-                    return returned_bucket;
+            let token_addr = reserve_fund.resource_address();
+            match self.reserves.get(&token_addr) {
+                Some(mut v) => {
+                    v.put(reserve_fund);
                 }
                 None => {
-                    panic!("No liquidity for this token is available")
+                    let v = Vault::with_bucket(reserve_fund);
+                    self.reserves.insert(token_addr, v);
                 }
+            };
+
+            // Return the change back to the component
+            returned_bucket
+        }
+
+        // asks the fee policy for the loan fee, falling back to loan_interest when there is none or it quotes nonsense
+        fn loan_fee(&self, currency: Address, amount: Decimal, borrower: Address) -> Decimal {
+            let default_fee = amount * self.loan_interest / 100;
+            match self.fee_policy {
+                Some(policy) => {
+                    let args = vec![
+                        scrypto_encode(&currency),
+                        scrypto_encode(&amount),
+                        scrypto_encode(&borrower)
+                    ];
+                    let fee: Decimal = Component::from(policy).call::<Decimal>("loan_fee", args);
+                    if fee < dec!(0) || fee > amount {default_fee} else {fee}
+                }
+                None => default_fee
             }
         }
 
-        // lends out all the cash in the vault
-        pub fn request_max_loan(&mut self, currency: Address, component_address: Address) -> Bucket {
+        // set to None to go back to the built-in loan_interest fee
+        #[auth(admin_badge)]
+        pub fn set_fee_policy(&mut self, fee_policy: Option<Address>) {
+            self.fee_policy = fee_policy;
+        }
+
+        pub fn get_fee_policy(&self) -> Option<Address> {
+            self.fee_policy
+        }
+
+        // cash in the vault that can be lent without dipping into the liquidity buffer or the cash backing the bank's cut
+        fn available_to_lend(&self, currency: Address) -> Decimal {
             match self.lender_accounts.get(&currency){
                 Some(acc) =>{
-                    let (vault, _resource) = acc;
-                    self.request_loan(vault.amount(), currency, component_address)
-                }
-                None =>{
-                    panic!("No liquidity for this token is available")
+                    let (vault, resource) = acc;
+                    let backing = vault.amount() + self.outstanding(currency);
+                    let buffer = backing * self.liquidity_buffer / 100;
+                    let reserved = match self.reserves.get(&resource.address()) {
+                        Some(reserve) if resource.total_supply() > dec!(0) => backing * reserve.amount() / resource.total_supply(),
+                        _ => dec!(0)
+                    };
+                    let held_back = buffer + reserved;
+                    if vault.amount() > held_back {vault.amount() - held_back} else {dec!(0)}
                 }
+                None => dec!(0)
             }
         }
 
-        // similar to request_loan but will loan max rather than fail if the amount cannot be filled
-        pub fn request_loan_upto(&mut self, amount: Decimal, currency: Address, component_address: Address) -> Bucket {
+        // amount of a currency currently loaned out and not yet repaid
+        fn outstanding(&self, currency: Address) -> Decimal {
+            match self.outstanding_loans.get(&currency){
+                Some(amount) => amount,
+                None => dec!(0)
+            }
+        }
+
+        // underlying tokens redeemable per lender token
+        pub fn get_exchange_rate(&self, currency: Address) -> Decimal {
             match self.lender_accounts.get(&currency){
                 Some(acc) =>{
-                    let (vault, _resource) = acc;
-                    self.request_loan(if amount > vault.amount(){vault.amount()}else{amount}, currency, component_address)
+                    let (vault, resource) = acc;
+                    if resource.total_supply() == dec!(0) {
+                        return dec!(1);
+                    }
+                    match self.successors.get(&currency){
+                        // migrated lender tokens are backed by the successor's lender tokens
+                        Some((successor, successor_tokens)) => {
+                            let successor_rate: Decimal = Component::from(successor).call::<Decimal>("get_exchange_rate", vec![scrypto_encode(&currency)]);
+                            successor_tokens.amount()*successor_rate/resource.total_supply()
+                        }
+                        None => (vault.amount() + self.outstanding(currency))/resource.total_supply()
+                    }
                 }
-                None =>{
-                    panic!("No liquidity for this token is available")
+                None => dec!(1)
+            }
+        }
+
+        // records the exchange rate the first time the bank is used in each epoch
+        fn snapshot_rate(&mut self, currency: Address) {
+            if self.lender_accounts.get(&currency).is_none() {
+                return;
+            }
+            let epoch = Context::current_epoch();
+            let (count, last_epoch) = match self.snapshot_counts.get(&currency){
+                Some(counts) => counts,
+                None => (0, 0)
+            };
+            if count > 0 && last_epoch == epoch {
+                return;
+            }
+            self.rate_history.insert((currency, count), (epoch, self.get_exchange_rate(currency)));
+            self.snapshot_counts.insert(currency, (count + 1, epoch));
+        }
+
+        // up to `limit` snapshots starting at the `offset`th oldest
+        pub fn get_rate_history(&self, currency: Address, offset: u64, limit: u64) -> Vec<(u64, Decimal)> {
+            let count = self.snapshot_count(currency);
+            let end = if offset.saturating_add(limit) < count {offset + limit} else {count};
+            (offset..end)
+                .map(|index| self.rate_history.get(&(currency, index)).unwrap())
+                .collect()
+        }
+
+        // annualized lender yield as a percentage over the last `epochs` epochs
+        pub fn get_apy(&self, currency: Address, epochs: u64) -> Decimal {
+            let now = Context::current_epoch();
+            let window_start = if epochs > now {0} else {now - epochs};
+
+            // snapshots are in epoch order, so binary search for the first one inside the window
+            let count = self.snapshot_count(currency);
+            let (mut low, mut high) = (0, count);
+            while low < high {
+                let mid = (low + high) / 2;
+                let (epoch, _rate) = self.rate_history.get(&(currency, mid)).unwrap();
+                if epoch < window_start {
+                    low = mid + 1;
+                }else{
+                    high = mid;
                 }
             }
+            match self.rate_history.get(&(currency, low)) {
+                Some(snapshot) => self.annualize(currency, snapshot),
+                None => dec!(0)
+            }
+        }
+
+        // annualized lender yield as a percentage since the first deposit of this currency
+        pub fn get_apy_since_inception(&self, currency: Address) -> Decimal {
+            match self.rate_history.get(&(currency, 0)) {
+                Some(snapshot) => self.annualize(currency, snapshot),
+                None => dec!(0)
+            }
+        }
+
+        fn snapshot_count(&self, currency: Address) -> u64 {
+            match self.snapshot_counts.get(&currency){
+                Some((count, _last_epoch)) => count,
+                None => 0
+            }
+        }
+
+        fn annualize(&self, currency: Address, snapshot: (u64, Decimal)) -> Decimal {
+            let (epoch, rate) = snapshot;
+            let elapsed = Context::current_epoch() - epoch;
+            if elapsed == 0 || rate == dec!(0) {
+                return dec!(0);
+            }
+            let growth = self.get_exchange_rate(currency)/rate - 1;
+            growth * 100 * Decimal::from(self.epochs_per_year) / Decimal::from(elapsed)
+        }
+
+        #[auth(admin_badge)]
+        pub fn set_epochs_per_year(&mut self, epochs_per_year: u64) {
+            assert!(epochs_per_year > 0, "There must be at least one epoch per year");
+            self.epochs_per_year = epochs_per_year;
         }
 
         pub fn get_balance(&self, currency: Address) -> Decimal {
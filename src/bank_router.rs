@@ -0,0 +1,208 @@
+use scrypto::prelude::*;
+
+#[derive(TypeId, Encode, Decode, Describe, PartialEq)]
+pub enum RoutingMode {
+    Weighted,   // split deposits across the banks by their weights
+    BestApy,    // send each deposit to the bank with the best recent APY
+}
+
+// spreads one currency over several banks while exposing the same deposit/withdraw interface as a single Bank
+blueprint! {
+    struct BankRouter {
+        currency: Address,
+        banks: Vec<(Address, Decimal)>, // (bank, weight)
+        lender_vaults: LazyMap<Address, Vault>, // <bank, lender tokens held at that bank>
+        router_badge: Vault,
+        share_resource: ResourceDef,
+        mode: RoutingMode,
+        apy_window: u64, // epochs of history BestApy compares
+        admin_badge: ResourceDef,
+    }
+
+    impl BankRouter {
+
+        pub fn new(currency: Address, mode: RoutingMode, apy_window: u64) -> (Component, Bucket) {
+            let router_badge: Bucket = ResourceBuilder::new_fungible(DIVISIBILITY_NONE).initial_supply_fungible(1);
+            let admin_badge: Bucket = ResourceBuilder::new_fungible(DIVISIBILITY_NONE).metadata("name", "Router Admin").initial_supply_fungible(1);
+
+            let token_meta = ResourceDef::from(currency).metadata();
+            let name = if token_meta.contains_key("name"){format!("R-{}",token_meta["name"])}else{"RouterToken".to_string()};
+            let symbol = if token_meta.contains_key("symbol"){format!("R-{}",token_meta["symbol"])}else{"RT".to_string()};
+            let share_resource: ResourceDef = ResourceBuilder::new_fungible(DIVISIBILITY_MAXIMUM)
+                .metadata("name", name)
+                .metadata("symbol", symbol)
+                .flags(MINTABLE | BURNABLE)
+                .badge(router_badge.resource_def(), MAY_MINT | MAY_BURN)
+                .metadata("description", "A share of a bank router's lender tokens")
+                .no_initial_supply();
+
+            let component = Self {
+                currency: currency,
+                banks: Vec::new(),
+                lender_vaults: LazyMap::new(),
+                router_badge: Vault::with_bucket(router_badge),
+                share_resource: share_resource,
+                mode: mode,
+                apy_window: apy_window,
+                admin_badge: admin_badge.resource_def(),
+            }
+            .instantiate();
+
+            (component, admin_badge)
+        }
+
+        #[auth(admin_badge)]
+        pub fn add_bank(&mut self, bank: Address, weight: Decimal) {
+            assert!(!self.banks.iter().any(|(b, _w)| *b == bank), "This bank is already registered");
+            assert!(weight >= dec!(0), "Weights cannot be negative");
+            self.banks.push((bank, weight));
+        }
+
+        // a weight of 0 stops new deposits going to a bank, existing funds are still withdrawn from it
+        #[auth(admin_badge)]
+        pub fn set_weight(&mut self, bank: Address, weight: Decimal) {
+            assert!(weight >= dec!(0), "Weights cannot be negative");
+            match self.banks.iter_mut().find(|(b, _w)| *b == bank) {
+                Some(entry) => entry.1 = weight,
+                None => panic!("This bank is not registered")
+            }
+        }
+
+        #[auth(admin_badge)]
+        pub fn set_mode(&mut self, mode: RoutingMode) {
+            self.mode = mode;
+        }
+
+        // deposits into the registered banks and mints router tokens at the current exchange rate
+        pub fn deposit(&mut self, payment: Bucket) -> Bucket {
+            assert!(payment.resource_address() == self.currency, "This router does not accept that token");
+            assert!(!self.banks.is_empty(), "No banks are registered");
+
+            let supply = self.share_resource.total_supply();
+            let value = self.total_value();
+            let shares_bought: Decimal = if supply > dec!(0) && value > dec!(0) { payment.amount()*supply/value } else { payment.amount() };
+
+            let mut payment = payment;
+            let routes = self.route(payment.amount());
+            for (bank, part) in routes.iter().cloned() {
+                if part > dec!(0) {
+                    let lenders: Bucket = Component::from(bank).call::<Bucket>("deposit", vec![scrypto_encode(&payment.take(part))]).into();
+                    match self.lender_vaults.get(&bank) {
+                        Some(mut v) => v.put(lenders),
+                        None => self.lender_vaults.insert(bank, Vault::with_bucket(lenders))
+                    }
+                }
+            }
+            // rounding dust goes to the first bank the deposit was routed to, never to one with a weight of 0
+            if payment.amount() > dec!(0) {
+                let bank = routes[0].0;
+                let lenders: Bucket = Component::from(bank).call::<Bucket>("deposit", vec![scrypto_encode(&payment)]).into();
+                match self.lender_vaults.get(&bank) {
+                    Some(mut v) => v.put(lenders),
+                    None => self.lender_vaults.insert(bank, Vault::with_bucket(lenders))
+                }
+            }
+
+            let mut share_resource = ResourceDef::from(self.share_resource.address());
+            self.router_badge.authorize(|auth|{
+                share_resource.mint(shares_bought, auth)
+            })
+        }
+
+        // burns router tokens and redeems the same share of the lender tokens held at every bank
+        pub fn withdraw(&mut self, shares: Bucket) -> Bucket {
+            assert!(shares.resource_address() == self.share_resource.address(), "Invalid router token");
+
+            let fraction: Decimal = shares.amount()/self.share_resource.total_supply();
+            let mut cash_returned = Bucket::new(self.currency);
+            for (bank, _weight) in self.banks.iter() {
+                match self.lender_vaults.get(bank) {
+                    Some(mut v) if v.amount() > dec!(0) => {
+                        let lenders = v.take(v.amount()*fraction);
+                        let cash: Bucket = Component::from(*bank).call::<Bucket>("withdraw", vec![scrypto_encode(&lenders)]).into();
+                        cash_returned.put(cash);
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut share_resource = ResourceDef::from(self.share_resource.address());
+            self.router_badge.authorize(|auth|{
+                share_resource.burn_with_auth(shares, auth);
+            });
+            cash_returned
+        }
+
+        // underlying tokens redeemable per router token
+        pub fn get_exchange_rate(&self, currency: Address) -> Decimal {
+            assert!(currency == self.currency, "This router does not accept that token");
+            let supply = self.share_resource.total_supply();
+            if supply > dec!(0) { self.total_value()/supply } else { dec!(1) }
+        }
+
+        // APY of the registered banks weighted by how much the router holds at each
+        pub fn get_apy(&self, currency: Address, epochs: u64) -> Decimal {
+            assert!(currency == self.currency, "This router does not accept that token");
+            let total = self.total_value();
+            if total == dec!(0) {
+                return dec!(0);
+            }
+            let mut weighted_apy = dec!(0);
+            for (bank, _weight) in self.banks.iter() {
+                let value = self.value_at(*bank);
+                if value > dec!(0) {
+                    weighted_apy = weighted_apy + value*self.bank_apy(*bank, epochs);
+                }
+            }
+            weighted_apy/total
+        }
+
+        pub fn get_banks(&self) -> Vec<(Address, Decimal)> {
+            self.banks.clone()
+        }
+
+        // how much of a deposit each bank receives, banks with a weight of 0 receive nothing in either mode
+        fn route(&self, amount: Decimal) -> Vec<(Address, Decimal)> {
+            let open: Vec<(Address, Decimal)> = self.banks.iter().filter(|(_b, w)| *w > dec!(0)).cloned().collect();
+            assert!(!open.is_empty(), "All bank weights are 0");
+            match self.mode {
+                RoutingMode::Weighted => {
+                    let total_weight = open.iter().fold(dec!(0), |sum, (_b, w)| sum + *w);
+                    open.iter().map(|(b, w)| (*b, amount * *w / total_weight)).collect()
+                }
+                RoutingMode::BestApy => {
+                    let mut best = open[0].0;
+                    let mut best_apy = self.bank_apy(best, self.apy_window);
+                    for (bank, _weight) in open.iter().skip(1) {
+                        let apy = self.bank_apy(*bank, self.apy_window);
+                        if apy > best_apy {
+                            best = *bank;
+                            best_apy = apy;
+                        }
+                    }
+                    vec![(best, amount)]
+                }
+            }
+        }
+
+        fn bank_apy(&self, bank: Address, epochs: u64) -> Decimal {
+            Component::from(bank).call::<Decimal>("get_apy", vec![scrypto_encode(&self.currency), scrypto_encode(&epochs)])
+        }
+
+        // underlying value of the lender tokens held at one bank
+        fn value_at(&self, bank: Address) -> Decimal {
+            match self.lender_vaults.get(&bank) {
+                Some(v) => {
+                    let rate: Decimal = Component::from(bank).call::<Decimal>("get_exchange_rate", vec![scrypto_encode(&self.currency)]);
+                    v.amount()*rate
+                }
+                None => dec!(0)
+            }
+        }
+
+        fn total_value(&self) -> Decimal {
+            self.banks.iter().fold(dec!(0), |sum, (bank, _w)| sum + self.value_at(*bank))
+        }
+
+    }
+}
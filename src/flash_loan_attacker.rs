@@ -0,0 +1,48 @@
+use scrypto::prelude::*;
+
+// borrower that tries to mint lender tokens at a deflated price while the bank's vault is lent out
+blueprint! {
+    struct FlashLoanAttacker {
+        vault: Vault,
+        lender_tokens: Option<Vault>,
+        bank: Address,
+        starting_cash: Decimal,
+    }
+
+    impl FlashLoanAttacker {
+
+        pub fn new(starting_cash: Bucket, bank: Address) -> Component {
+            Self {
+                starting_cash: starting_cash.amount(),
+                vault: Vault::with_bucket(starting_cash),
+                lender_tokens: None,
+                bank: bank,
+            }
+            .instantiate()
+        }
+
+        pub fn execute(&mut self, money: Bucket) -> Bucket {
+            // deposit while the loan is outstanding, hoping for a cheap exchange rate
+            let stake = self.vault.take(self.starting_cash/2);
+            let lenders: Bucket = Component::from(self.bank).call::<Bucket>("deposit", vec![scrypto_encode(&stake)]).into();
+            self.lender_tokens = Some(Vault::with_bucket(lenders));
+
+            let mut returned_bucket = self.vault.take(money.amount()/100);
+            returned_bucket.put(money);
+            returned_bucket
+        }
+
+        // redeems the lender tokens after repayment, failing if the attack made a profit
+        pub fn cash_out(&mut self) -> Bucket {
+            let lenders = match &mut self.lender_tokens {
+                Some(vault) => vault.take_all(),
+                None => panic!("No lender tokens to redeem")
+            };
+            let cash: Bucket = Component::from(self.bank).call::<Bucket>("withdraw", vec![scrypto_encode(&lenders)]).into();
+            self.vault.put(cash);
+            assert!(self.vault.amount() <= self.starting_cash, "Flash loan attack was profitable");
+            self.vault.take_all()
+        }
+
+    }
+}
@@ -1,3 +1,7 @@
 mod bank;
+mod bank_router;
 mod bank_tester;
+mod flash_loan_attacker;
+mod nft_pool;
+mod nft_tester;
 mod savings_account;
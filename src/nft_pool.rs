@@ -0,0 +1,203 @@
+use scrypto::prelude::*;
+
+// receipt for one pooled nft, it has the nft's key and redeems exactly that nft
+#[derive(NonFungibleData)]
+pub struct PoolReceipt {
+    collection: Address,
+}
+
+// pools non-fungibles of listed collections, flash lends them out for a fee and pays the fees to depositors
+blueprint! {
+    struct NftPool {
+        fee_resource: Address,
+        fee_per_nft: Decimal,
+        pool_badge: Vault,
+        collections: LazyMap<Address, (Vault, ResourceDef, Vault, Vault)>, // <CollectionAddr, (pooled nfts, receipt resource, redeemed receipts, collected fees)>
+        receipt_lookup: LazyMap<Address, Address>, // <ReceiptAddr, CollectionAddr>
+        outstanding_loans: LazyMap<Address, Decimal>, // <CollectionAddr, nfts currently flash loaned out>
+        admin_badge: ResourceDef,
+    }
+
+    impl NftPool {
+
+        pub fn new(fee_resource: Address, fee_per_nft: Decimal) -> (Component, Bucket) {
+            let pool_badge: Bucket = ResourceBuilder::new_fungible(DIVISIBILITY_NONE).initial_supply_fungible(1);
+            let admin_badge: Bucket = ResourceBuilder::new_fungible(DIVISIBILITY_NONE).metadata("name", "NFT Pool Admin").initial_supply_fungible(1);
+
+            let component = Self {
+                fee_resource: fee_resource,
+                fee_per_nft: fee_per_nft,
+                pool_badge: Vault::with_bucket(pool_badge),
+                collections: LazyMap::new(),
+                receipt_lookup: LazyMap::new(),
+                outstanding_loans: LazyMap::new(),
+                admin_badge: admin_badge.resource_def(),
+            }
+            .instantiate();
+
+            (component, admin_badge)
+        }
+
+        #[auth(admin_badge)]
+        pub fn list_collection(&mut self, collection: Address) {
+            assert!(self.collections.get(&collection).is_none(), "This collection is already listed");
+            match ResourceDef::from(collection).resource_type() {
+                ResourceType::NonFungible => {}
+                _ => panic!("Only non-fungible collections can be pooled")
+            }
+
+            let token_meta = ResourceDef::from(collection).metadata();
+            let name = if token_meta.contains_key("name"){format!("P-{}",token_meta["name"])}else{"PoolReceipt".to_string()};
+            let symbol = if token_meta.contains_key("symbol"){format!("P-{}",token_meta["symbol"])}else{"PR".to_string()};
+            let receipt_resource: ResourceDef = ResourceBuilder::new_non_fungible()
+                .metadata("name", name)
+                .metadata("symbol", symbol)
+                .flags(MINTABLE)
+                .badge(self.pool_badge.resource_def(), MAY_MINT)
+                .metadata("description", "A receipt for an NFT in an NFT pool, redeemable for the NFT with the same key")
+                .no_initial_supply();
+
+            let receipt_address = receipt_resource.address();
+            self.receipt_lookup.insert(receipt_address, collection);
+            self.collections.insert(collection, (Vault::new(collection), receipt_resource, Vault::new(receipt_address), Vault::new(self.fee_resource)));
+        }
+
+        #[auth(admin_badge)]
+        pub fn set_fee(&mut self, fee_per_nft: Decimal) {
+            assert!(fee_per_nft >= dec!(0), "The fee cannot be negative");
+            self.fee_per_nft = fee_per_nft;
+        }
+
+        // fees a depositor of `count` nfts has to pay in so they don't dilute fees already earned by the pool
+        pub fn get_buy_in(&self, collection: Address, count: Decimal) -> Decimal {
+            let (_nfts, _receipt_resource, _redeemed, fees) = self.collection(collection);
+            let pooled = self.pooled(collection);
+            if pooled == dec!(0) { dec!(0) } else { fees.amount()*count/pooled }
+        }
+
+        // mints a receipt with the same key for every nft, returns the receipts and the change of the buy-in
+        pub fn deposit(&mut self, nfts: Bucket, buy_in: Bucket) -> Vec<Bucket> {
+            let collection = nfts.resource_address();
+            let (mut vault, mut receipt_resource, mut redeemed, mut fees) = self.collection(collection);
+            assert!(buy_in.resource_address() == self.fee_resource, "The buy-in must be paid in the fee resource");
+
+            let mut buy_in = buy_in;
+            let required = self.get_buy_in(collection, nfts.amount());
+            assert!(buy_in.amount() >= required, "A buy-in of {} is required", required);
+            fees.put(buy_in.take(required));
+
+            // a key can only be minted once, so the receipts of redeemed nfts are handed out again
+            let redeemed_keys = redeemed.get_non_fungible_keys();
+            let mut receipts = Bucket::new(receipt_resource.address());
+            for key in nfts.get_non_fungible_keys() {
+                if redeemed_keys.contains(&key) {
+                    receipts.put(redeemed.take_non_fungible(&key));
+                }else{
+                    let receipt = self.pool_badge.authorize(|auth|{
+                        receipt_resource.mint_non_fungible(&key, PoolReceipt { collection }, auth)
+                    });
+                    receipts.put(receipt);
+                }
+            }
+            vault.put(nfts);
+            vec![receipts, buy_in]
+        }
+
+        // takes back receipts for the nfts with the same keys plus their share of the fees
+        pub fn withdraw(&mut self, receipts: Bucket) -> Vec<Bucket> {
+            let collection = match self.receipt_lookup.get(&receipts.resource_address()){
+                Some(addr) => {addr}
+                None => {panic!("Invalid pool receipt")}
+            };
+            let (mut vault, _receipt_resource, mut redeemed, mut fees) = self.collection(collection);
+            let keys = receipts.get_non_fungible_keys();
+            let pooled_keys = vault.get_non_fungible_keys();
+            assert!(keys.iter().all(|key| pooled_keys.contains(key)), "Those nfts are loaned out");
+
+            let fee_share = fees.amount()*receipts.amount()/self.pooled(collection);
+            let mut nfts = Bucket::new(collection);
+            for key in keys.iter() {
+                nfts.put(vault.take_non_fungible(key));
+            }
+
+            redeemed.put(receipts);
+            vec![nfts, fees.take(fee_share)]
+        }
+
+        // flash lends the given nfts, the fee is paid upfront and its change is returned
+        pub fn request_loan(&mut self, collection: Address, keys: BTreeSet<NonFungibleKey>, component_address: Address, fee: Bucket) -> Bucket {
+            let (mut vault, _receipt_resource, _redeemed, mut fees) = self.collection(collection);
+            let count = Decimal::from(keys.len() as u64);
+            assert!(count > dec!(0), "No nfts were requested");
+
+            let mut fee = fee;
+            let amount_to_take = self.fee_per_nft*count;
+            assert!(fee.amount() >= amount_to_take, "You have to pay a fee of {}", amount_to_take);
+            fees.put(fee.take(amount_to_take));
+
+            let mut loan = Bucket::new(collection);
+            for key in keys.iter() {
+                loan.put(vault.take_non_fungible(key));
+            }
+
+            let outstanding = self.outstanding(collection);
+            self.outstanding_loans.insert(collection, outstanding + count);
+
+            let args = vec![
+                scrypto_encode(&loan)
+            ];
+            let returned_bucket: Bucket = Component::from(component_address).call::<Bucket>("execute", args).into();
+
+            // Make sure every lent nft came back
+            assert!(returned_bucket.resource_address() == collection, "The loaned nfts must be returned");
+            assert!(returned_bucket.amount() == count, "Exactly the loaned nfts must be returned");
+            let returned_keys = returned_bucket.get_non_fungible_keys();
+            assert!(keys.iter().all(|key| returned_keys.contains(key)), "The loaned nfts must be returned");
+            vault.put(returned_bucket);
+            self.outstanding_loans.insert(collection, outstanding);
+
+            fee
+        }
+
+        // one receipt always redeems one nft, so receipts can be held like lender tokens
+        pub fn get_exchange_rate(&self, collection: Address) -> Decimal {
+            self.collection(collection);
+            dec!(1)
+        }
+
+        pub fn get_fee_resource(&self) -> Address {
+            self.fee_resource
+        }
+
+        pub fn get_fees(&self, collection: Address) -> Decimal {
+            let (_nfts, _receipt_resource, _redeemed, fees) = self.collection(collection);
+            fees.amount()
+        }
+
+        pub fn get_balance(&self, collection: Address) -> Decimal {
+            let (nfts, _receipt_resource, _redeemed, _fees) = self.collection(collection);
+            nfts.amount()
+        }
+
+        fn collection(&self, collection: Address) -> (Vault, ResourceDef, Vault, Vault) {
+            match self.collections.get(&collection){
+                Some(c) => c,
+                None => panic!("This collection is not listed")
+            }
+        }
+
+        // nfts backing the receipts, including those loaned out
+        fn pooled(&self, collection: Address) -> Decimal {
+            let (nfts, _receipt_resource, _redeemed, _fees) = self.collection(collection);
+            nfts.amount() + self.outstanding(collection)
+        }
+
+        fn outstanding(&self, collection: Address) -> Decimal {
+            match self.outstanding_loans.get(&collection){
+                Some(count) => count,
+                None => dec!(0)
+            }
+        }
+
+    }
+}
@@ -0,0 +1,40 @@
+use scrypto::prelude::*;
+
+#[derive(NonFungibleData)]
+pub struct TestNft {
+    serial: u64,
+}
+
+// holds a small nft collection for tests and flash borrows nfts, handing them straight back
+blueprint! {
+    struct NftTester {
+        nfts: Vault
+    }
+
+    impl NftTester {
+
+        // mints `count` nfts keyed by their serial number as big-endian bytes
+        pub fn new(count: u64) -> Component {
+            let nfts: Bucket = ResourceBuilder::new_non_fungible()
+                .metadata("name", "Test NFT")
+                .initial_supply_non_fungible((0..count).map(|serial| (NonFungibleKey::new(serial.to_be_bytes().to_vec()), TestNft { serial })));
+            Self {
+                nfts: Vault::with_bucket(nfts),
+            }
+            .instantiate()
+        }
+
+        pub fn take(&mut self, keys: BTreeSet<NonFungibleKey>) -> Bucket {
+            let mut bucket = Bucket::new(self.nfts.resource_address());
+            for key in keys.iter() {
+                bucket.put(self.nfts.take_non_fungible(key));
+            }
+            bucket
+        }
+
+        pub fn execute(&mut self, nfts: Bucket) -> Bucket {
+            nfts
+        }
+
+    }
+}
@@ -1,39 +1,275 @@
 use scrypto::prelude::*;
 
+#[derive(TypeId, Encode, Decode, Describe)]
+pub enum BufferTarget {
+    Percent(Decimal),   // percentage of the token's total balance kept in the local vault
+    Fixed(Decimal),     // amount of the token kept in the local vault
+}
+
+#[derive(TypeId, Encode, Decode, Describe, PartialEq)]
+pub enum EarningsRoute {
+    Compound,   // back into the vault that was lent out
+    Separate,   // into a separate earnings vault the owner can withdraw from
+    Bank,       // deposited at the token's bank, compounding locally when the token isn't banked
+}
+
+// per-token settings, the tokens themselves live in the matching local vault
+#[derive(TypeId, Encode, Decode, Describe)]
+pub struct TokenSlot {
+    pub bank_token_address: Option<Address>,    // lender token received from the bank
+    pub bank: Option<Address>,                  // bank this token is banked at, the account's default bank when None
+    pub is_using_bank: bool,
+    pub is_borrowable: bool,                    // whether the local vault can be flash loaned
+    pub loan_interest: Decimal,
+    pub buffer_target: Option<BufferTarget>,    // how much of a banked token stays liquid locally
+    pub rebalance_threshold: Option<Decimal>,   // percentage of the total balance the buffer may drift before rebalancing
+    pub daily_limit: Option<Decimal>,           // most that can be withdrawn per epoch, None for no limit
+    pub loan_earnings: Decimal,                 // cumulative interest earned from flash loans of the local vault
+    pub earnings_route: EarningsRoute,
+    pub lend_banked: bool,                      // whether flash loans may redeem lender tokens when the local vault is short
+    pub multisig_threshold: Option<Decimal>,    // withdrawals above this need a quorum of owner keys, every withdrawal does when None
+    pub withdrawn_this_epoch: Decimal,          // counted against daily_limit
+    pub limit_epoch: u64,                       // epoch withdrawn_this_epoch belongs to
+    pub pending_limit: Option<(Option<Decimal>, u64)>, // raised limit and the epoch it takes effect
+    pub history_minimum: Decimal,               // deposits and flash loans below this are left out of the history
+    pub pending_bank: Option<(Address, u64)>,   // bank chosen with set_bank_for_token and the epoch the switch can be finished
+}
+
+impl TokenSlot {
+    pub fn new() -> Self {
+        Self {
+            bank_token_address: None,
+            bank: None,
+            is_using_bank: false,
+            is_borrowable: false,
+            loan_interest: dec!("0.09"),
+            buffer_target: None,
+            rebalance_threshold: None,
+            daily_limit: None,
+            loan_earnings: dec!(0),
+            earnings_route: EarningsRoute::Compound,
+            lend_banked: false,
+            multisig_threshold: None,
+            withdrawn_this_epoch: dec!(0),
+            limit_epoch: 0,
+            pending_limit: None,
+            history_minimum: dec!(0),
+            pending_bank: None,
+        }
+    }
+}
+
+#[derive(TypeId, Encode, Decode, Describe)]
+pub struct TokenBalance {
+    pub resource_address: Address,
+    pub local: Decimal,             // amount in the local vault
+    pub lender_tokens: Decimal,     // lender tokens held for this token
+    pub underlying: Decimal,        // what the lender tokens are worth at the bank's current exchange rate
+    pub is_using_bank: bool,
+    pub is_borrowable: bool,
+    pub loan_interest: Decimal,
+}
+
+// a guardian-approved rotation of a lost owner key
+#[derive(TypeId, Encode, Decode, Describe, Clone)]
+pub struct Recovery {
+    pub old_key: EcdsaPublicKey,
+    pub new_key: EcdsaPublicKey,
+    pub approvals: Vec<EcdsaPublicKey>, // guardians that approved so far
+    pub started_epoch: u64,
+}
+
+// what a spender may pull of one token
+#[derive(TypeId, Encode, Decode, Describe)]
+pub struct Allowance {
+    pub amount: Decimal,    // spendable in total, or per epoch when per_epoch is set
+    pub per_epoch: bool,
+    pub spent: Decimal,     // spent so far, or in `epoch` when per_epoch is set
+    pub epoch: u64,
+}
+
+// a standing order paid by execute_due_payments
+#[derive(TypeId, Encode, Decode, Describe, Clone)]
+pub struct ScheduledPayment {
+    pub id: u64,
+    pub recipient: Address,         // component with a deposit(Bucket) method
+    pub resource_address: Address,
+    pub amount: Decimal,
+    pub interval: u64,              // epochs between payments
+    pub next_epoch: u64,
+    pub end_epoch: Option<u64>,     // no payments are made after this epoch
+}
+
+// an amount of a token that can't be withdrawn until the unlock epoch or target balance is reached
+#[derive(TypeId, Encode, Decode, Describe, Clone)]
+pub struct SavingsGoal {
+    pub id: u64,
+    pub resource_address: Address,
+    pub amount: Decimal,
+    pub unlock_epoch: Option<u64>,
+    pub target_balance: Option<Decimal>,   // total balance, local and banked, that completes the goal
+    pub reached: bool,                      // reported by get_goals, a goal only counts as reached while the balance stays at the target
+}
+
+// a named part of a token's balance, the rest of the balance stays unallocated
+#[derive(TypeId, Encode, Decode, Describe, Clone)]
+pub struct Envelope {
+    pub name: String,
+    pub resource_address: Address,
+    pub balance: Decimal,
+    pub is_banked: bool,                    // whether this envelope's funds may be lent at the token's bank
+    pub limit: Option<Decimal>,             // most that can be withdrawn from the envelope per epoch
+    pub withdrawn_this_epoch: Decimal,
+    pub limit_epoch: u64,
+    pub unlock_epoch: Option<u64>,          // the envelope can't be withdrawn from before this epoch
+    pub target_balance: Option<Decimal>,    // ... or before its balance reaches this
+    pub reached: bool,                      // the goal was reached and the envelope stays unlocked
+}
+
+// history keeps the newest entries only, older ones are overwritten
+const MAX_HISTORY: u64 = 500;
+
+// goals are checked on every withdrawal, so only this many can be open at once
+const MAX_GOALS: usize = 50;
+
+// epochs a raised limit or a new bank waits by default, a day of hourly epochs
+const DEFAULT_LIMIT_INCREASE_DELAY: u64 = 24;
+
+#[derive(TypeId, Encode, Decode, Describe, Clone)]
+pub enum HistoryKind {
+    Deposit,
+    Withdrawal,
+    Pull,               // counterparty is the spender badge
+    ScheduledPayment,   // counterparty is the recipient
+    FlashLoan,          // counterparty is the borrowing component
+    Bank,               // counterparty is the bank, amount is the balance at the time
+    Unbank,
+    Inheritance,        // counterparty is the beneficiary badge
+}
+
+#[derive(TypeId, Encode, Decode, Describe, Clone)]
+pub struct HistoryEntry {
+    pub id: u64,    // position in the full history, also counting overwritten entries
+    pub kind: HistoryKind,
+    pub resource_address: Address,
+    pub amount: Decimal,
+    pub epoch: u64,
+    pub counterparty: Option<Address>,
+}
+
 blueprint! {
     struct SavingsAccount {
-        public_key: EcdsaPublicKey,
-        vaults: LazyMap<Address, (Vault, Option<Address>, bool, bool, Decimal)>, // (localStorage, bankTokenAddress, isUsingBank, localStorageIsBorrowable, loanInterest)
-        bank: Address,
+        owner_keys: Vec<EcdsaPublicKey>,
+        quorum: u64, // owner signatures needed for large withdrawals and owner management
+        owner_badge: Option<(Address, Option<NonFungibleKey>)>, // badge whose holder owns the account, optionally one specific non-fungible
+        guardians: Vec<EcdsaPublicKey>,
+        guardian_quorum: u64, // guardian approvals needed to rotate an owner key
+        recovery_delay: u64, // epochs between starting and finishing a recovery, for the owner to cancel it
+        recovery: Option<Recovery>,
+        limit_increase_delay: u64, // epochs before a raised withdrawal limit takes effect
+        pending_delay: Option<(u64, u64)>, // shorter limit increase delay and the epoch it takes effect
+        allowances: LazyMap<(Address, Address), Allowance>, // <(spenderBadge, TokenAddr), allowance>
+        payments: Vec<ScheduledPayment>,
+        next_payment_id: u64,
+        goals: Vec<SavingsGoal>,
+        next_goal_id: u64,
+        beneficiaries: Vec<(Address, u64)>, // <(beneficiaryBadge, share)>
+        inactivity_period: Option<u64>, // epochs without owner activity before beneficiaries can claim, None disables inheritance
+        last_activity: u64, // epoch of the last owner-authorized call
+        inheritance_round: u64, // bumped when the beneficiaries change, or the owner is back after a claim, so earlier claims don't carry over
+        round_claimed: bool, // a beneficiary claimed in the current round
+        pending_inheritance: Option<(Vec<(Address, u64)>, Option<u64>, u64)>, // beneficiaries and inactivity period set with set_beneficiaries and the epoch they take effect
+        claims: LazyMap<(u64, Address, Address), bool>, // <(round, beneficiaryBadge, TokenAddr), claimed>
+        claimed_shares: LazyMap<(u64, Address), u64>, // <(round, TokenAddr), shares already paid out>
+        envelopes: LazyMap<(Address, String), Envelope>, // <(TokenAddr, name), envelope>
+        envelope_names: LazyMap<Address, Vec<String>>, // envelopes of each token, since LazyMap can't be iterated
+        history: LazyMap<u64, HistoryEntry>, // ring buffer of the last MAX_HISTORY entries, keyed by id % MAX_HISTORY
+        history_count: u64,
+        vaults: LazyMap<Address, Vault>,
+        earnings: LazyMap<Address, Vault>, // flash loan interest kept apart with EarningsRoute::Separate
+        slots: LazyMap<Address, TokenSlot>,
+        lender_lookup: LazyMap<Address, Address>, // <LenderTokenAddr, TokenAddr>
+        tokens: LazyMap<u64, Address>, // every address in slots by index, since LazyMap can't be iterated
+        token_count: u64,
+        bank: Address, // default bank for tokens without one of their own
     }
 
     impl SavingsAccount {
         // TODO: at 0.4.0 revert to:
         // pub fn new(public_key: EcdsaPublicKey) -> Component {
         pub fn new(public_key: String, bank: Address) -> Component {
-            Self {
-                // TODO: at 0.4.0 revert to:
-                // public_key,
-                public_key: EcdsaPublicKey::from_str(public_key.as_str()).unwrap(),
-                vaults: LazyMap::new(),
-                bank
-            }
-            .instantiate()
+            // TODO: at 0.4.0 revert to:
+            // Self::with_owners(vec![public_key], 1, bank).instantiate()
+            Self::with_owners(vec![EcdsaPublicKey::from_str(public_key.as_str()).unwrap()], 1, bank).instantiate()
         }
 
         // TODO: at 0.4.0 revert to:
         // pub fn with_bucket(public_key: EcdsaPublicKey, bucket: Bucket) -> Component {
         pub fn with_bucket(public_key: String, bucket: Bucket, bank: Address) -> Component {
-            let vaults = LazyMap::new();
-            vaults.insert(bucket.resource_address(), (Vault::with_bucket(bucket), None, false, false, dec!("0.09")));
+            // TODO: at 0.4.0 revert to:
+            // let mut account = Self::with_owners(vec![public_key], 1, bank);
+            let mut account = Self::with_owners(vec![EcdsaPublicKey::from_str(public_key.as_str()).unwrap()], 1, bank);
+            account.deposit(bucket);
+            account.instantiate()
+        }
+
+        // an account where large withdrawals and owner changes need `quorum` of the keys to sign
+        pub fn new_multisig(public_keys: Vec<String>, quorum: u64, bank: Address) -> Component {
+            let owner_keys: Vec<EcdsaPublicKey> = public_keys.iter().map(|key| EcdsaPublicKey::from_str(key.as_str()).unwrap()).collect();
+            assert!(quorum > 0 && quorum as usize <= owner_keys.len(), "The quorum must be between 1 and the number of keys");
+            Self::with_owners(owner_keys, quorum, bank).instantiate()
+        }
+
+        // an account owned by whoever holds the badge, so components and DAOs can own one
+        pub fn with_owner_badge(owner_badge: Address, bank: Address) -> Component {
+            let mut account = Self::with_owners(Vec::new(), 1, bank);
+            account.owner_badge = Some((owner_badge, None));
+            account.instantiate()
+        }
+
+        // an account owned by whoever holds one specific non-fungible of the badge resource
+        pub fn with_owner_non_fungible(owner_badge: Address, key: NonFungibleKey, bank: Address) -> Component {
+            let mut account = Self::with_owners(Vec::new(), 1, bank);
+            account.owner_badge = Some((owner_badge, Some(key)));
+            account.instantiate()
+        }
 
+        fn with_owners(owner_keys: Vec<EcdsaPublicKey>, quorum: u64, bank: Address) -> Self {
             Self {
-                // TODO: at 0.4.0 revert to:
-                // public_key,
-                public_key: EcdsaPublicKey::from_str(public_key.as_str()).unwrap(),
-                vaults,
-                bank
-            }.instantiate()
+                owner_keys,
+                quorum,
+                owner_badge: None,
+                guardians: Vec::new(),
+                guardian_quorum: 0,
+                recovery_delay: 0,
+                recovery: None,
+                limit_increase_delay: DEFAULT_LIMIT_INCREASE_DELAY,
+                pending_delay: None,
+                allowances: LazyMap::new(),
+                payments: Vec::new(),
+                next_payment_id: 0,
+                goals: Vec::new(),
+                next_goal_id: 0,
+                beneficiaries: Vec::new(),
+                inactivity_period: None,
+                last_activity: Context::current_epoch(),
+                inheritance_round: 0,
+                round_claimed: false,
+                pending_inheritance: None,
+                claims: LazyMap::new(),
+                claimed_shares: LazyMap::new(),
+                envelopes: LazyMap::new(),
+                envelope_names: LazyMap::new(),
+                history: LazyMap::new(),
+                history_count: 0,
+                vaults: LazyMap::new(),
+                earnings: LazyMap::new(),
+                slots: LazyMap::new(),
+                lender_lookup: LazyMap::new(),
+                tokens: LazyMap::new(),
+                token_count: 0,
+                bank,
+            }
         }
 
         /// Deposit a batch of buckets into this account
@@ -46,62 +282,486 @@ blueprint! {
         /// Deposits resource into this account.
         pub fn deposit(&mut self, bucket: Bucket) {
             let address = bucket.resource_address();
-            match self.vaults.get(&address) {
-                Some( (mut vault, bank_token_address, is_using_bank, _local_storage_is_borrowable, _loan_interest) ) => {
-                    if is_using_bank {
-                        let (mut v, _bta, _iub, _lsb, _li) = self.vaults.get(&bank_token_address.unwrap()).unwrap();
-                        let args = vec![
-                            scrypto_encode(&bucket)
-                        ];
-                        let lended_tokens: Bucket = Component::from(self.bank).call::<Bucket>("deposit", args).into();
-                        v.put(lended_tokens);
-                    }else{
-                        vault.put(bucket);
+            let amount = bucket.amount();
+            self.store(bucket);
+            self.record_permissionless(HistoryKind::Deposit, address, amount, None);
+        }
+
+        // puts tokens into the account without a history entry, for lender tokens, change and fees coming back from banks
+        fn store(&mut self, bucket: Bucket) {
+            let address = bucket.resource_address();
+            match self.slots.get(&address) {
+                Some(slot) => {
+                    self.local_vault(address).put(bucket);
+                    if slot.is_using_bank {
+                        // fill the liquid buffer first and bank the rest once it drifts past the threshold
+                        self.rebalance_if_drifted(address, false);
                     }
                 }
                 None => {
-                    let v = Vault::with_bucket(bucket);
-                    self.vaults.insert(address, (v, None, false, false, dec!("0.09")));
+                    self.vaults.insert(address, Vault::with_bucket(bucket));
+                    self.slots.insert(address, TokenSlot::new());
+                    self.tokens.insert(self.token_count, address);
+                    self.token_count += 1;
+                }
+            }
+        }
+
+        // number of owner keys that signed the transaction, presenting the owner badge counts as a full quorum
+        fn signers(&self, account_auth: &BucketRef) -> u64 {
+            match &self.owner_badge {
+                Some((badge, key)) if account_auth.resource_address() == *badge && account_auth.amount() > dec!(0) => {
+                    let holds_badge = match key {
+                        Some(key) => account_auth.get_non_fungible_keys().contains(key),
+                        None => true
+                    };
+                    if holds_badge {
+                        return self.quorum;
+                    }
                 }
+                _ => {}
+            }
+            if account_auth.resource_address() != ECDSA_TOKEN {
+                return 0;
             }
+            let signed = account_auth.get_non_fungible_keys();
+            self.owner_keys.iter().filter(|key| signed.contains(&NonFungibleKey::new(key.to_vec()))).count() as u64
         }
 
-        fn non_fungible_key(&self) -> NonFungibleKey {
-            NonFungibleKey::new(self.public_key.to_vec())
+        // any owner-authorized call resets the inactivity timer of the inheritance
+        fn check_owner(&mut self, account_auth: BucketRef) {
+            assert!(self.signers(&account_auth) > 0, "Not authorized");
+            account_auth.drop();
+            self.record_activity();
         }
 
-        /// Withdraws resource from this account.
-        pub fn withdraw(
+        fn check_quorum(&mut self, account_auth: BucketRef) {
+            assert!(self.signers(&account_auth) >= self.quorum, "{} owner signatures are required", self.quorum);
+            account_auth.drop();
+            self.record_activity();
+        }
+
+        // an owner back after a claim starts a new round, so a later claim splits the whole balance again
+        fn record_activity(&mut self) {
+            self.apply_pending_inheritance();
+            self.last_activity = Context::current_epoch();
+            if self.round_claimed {
+                self.inheritance_round += 1;
+                self.round_claimed = false;
+            }
+        }
+
+        // withdrawals above the token's multisig threshold, or any withdrawal without one, need a quorum, anything else a single owner key
+        // also counts the withdrawal against the token's limit for this epoch
+        fn check_withdrawal(&mut self, resource_address: Address, amount: Decimal, account_auth: BucketRef) {
+            self.check_signers(resource_address, amount, account_auth);
+            self.record_withdrawal(resource_address, amount);
+        }
+
+        fn check_signers(&mut self, resource_address: Address, amount: Decimal, account_auth: BucketRef) {
+            match self.slot(resource_address).multisig_threshold {
+                Some(threshold) if amount <= threshold => self.check_owner(account_auth),
+                _ => self.check_quorum(account_auth)
+            }
+        }
+
+        // refuses withdrawals that would dip into envelopes or savings goals, then counts them against the limit
+        fn record_withdrawal(&mut self, resource_address: Address, amount: Decimal) {
+            self.prune_goals();
+            let balance_after = self.total_balance(resource_address) - amount;
+            let free = self.unallocated(resource_address) - self.locked_amount(resource_address, balance_after);
+            assert!(free >= amount, "Only {} of this token is outside envelopes and savings goals", free);
+            self.count_against_limit(resource_address, amount);
+        }
+
+        fn count_against_limit(&mut self, resource_address: Address, amount: Decimal) {
+            self.assert_not_lender_token(resource_address);
+            let mut slot = self.current_limit(resource_address);
+            if let Some(limit) = slot.daily_limit {
+                assert!(slot.withdrawn_this_epoch + amount <= limit, "Withdrawal limit of {} per epoch exceeded", limit);
+            }
+            slot.withdrawn_this_epoch = slot.withdrawn_this_epoch + amount;
+            self.slots.insert(resource_address, slot);
+        }
+
+        // lender tokens are withdrawn through the token they were issued for, under that token's limits and locks
+        fn assert_not_lender_token(&self, resource_address: Address) {
+            if let Some(token) = self.lender_lookup.get(&resource_address) {
+                panic!("Lender tokens can't be withdrawn directly, withdraw {} instead", token);
+            }
+        }
+
+        // the slot with any due limit increase applied and the withdrawal count reset for a new epoch
+        fn current_limit(&self, resource_address: Address) -> TokenSlot {
+            let mut slot = self.slot(resource_address);
+            let epoch = Context::current_epoch();
+            if let Some((limit, effective_epoch)) = slot.pending_limit {
+                if epoch >= effective_epoch {
+                    slot.daily_limit = limit;
+                    slot.pending_limit = None;
+                }
+            }
+            if slot.limit_epoch != epoch {
+                slot.limit_epoch = epoch;
+                slot.withdrawn_this_epoch = dec!(0);
+            }
+            slot
+        }
+
+        // lowering a limit applies immediately, raising or removing it only after the limit increase delay
+        pub fn set_withdrawal_limit(
             &mut self,
-            amount: Decimal,
             resource_address: Address,
+            limit: Option<Decimal>,
             account_auth: BucketRef,
-        ) -> Bucket {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
-
-            let vault = self.vaults.get(&resource_address);
-            match vault {
-                Some( (mut vault, bank_token_address, is_using_bank, _local_storage_is_borrowable, _loan_interest) ) => {
-                    if is_using_bank {
-                        let (mut v, _bta, _iub, _lsb, _li) = self.vaults.get(&bank_token_address.unwrap()).unwrap();
-                        let args = vec![
-                            scrypto_encode(&v.take(v.amount()))
-                        ];
-                        let mut base_tokens: Bucket = Component::from(self.bank).call::<Bucket>("withdraw", args).into();
-                        let withdrawn_tokens = base_tokens.take(amount);
-                        let lended_tokens: Bucket = Component::from(self.bank).call::<Bucket>("deposit", vec![scrypto_encode(&base_tokens)]).into();
-                        v.put(lended_tokens);
-                        return withdrawn_tokens;
-                    }else{
-                        vault.take(amount)
+        ) -> () {
+            self.check_owner(account_auth);
+
+            let mut slot = self.current_limit(resource_address);
+            let is_increase = match (slot.daily_limit, limit) {
+                (Some(current), Some(new)) => new > current,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if is_increase {
+                slot.pending_limit = Some((limit, Context::current_epoch() + self.increase_delay()));
+            }else{
+                slot.daily_limit = limit;
+                slot.pending_limit = None;
+            }
+            self.slots.insert(resource_address, slot);
+        }
+
+        // shortening the delay is itself delayed, otherwise a stolen key could skip it
+        pub fn set_limit_increase_delay(&mut self, delay: u64, account_auth: BucketRef) -> () {
+            self.check_quorum(account_auth);
+
+            let current = self.increase_delay();
+            self.limit_increase_delay = current;
+            if delay >= current {
+                self.limit_increase_delay = delay;
+                self.pending_delay = None;
+            }else{
+                self.pending_delay = Some((delay, Context::current_epoch() + current));
+            }
+        }
+
+        fn increase_delay(&self) -> u64 {
+            match self.pending_delay {
+                Some((delay, effective_epoch)) if Context::current_epoch() >= effective_epoch => delay,
+                _ => self.limit_increase_delay
+            }
+        }
+
+        pub fn add_owner_key(&mut self, public_key: String, account_auth: BucketRef) -> () {
+            self.check_quorum(account_auth);
+
+            let key = EcdsaPublicKey::from_str(public_key.as_str()).unwrap();
+            assert!(!self.owner_keys.contains(&key), "That key is already an owner");
+            self.owner_keys.push(key);
+        }
+
+        pub fn remove_owner_key(&mut self, public_key: String, account_auth: BucketRef) -> () {
+            self.check_quorum(account_auth);
+
+            let key = EcdsaPublicKey::from_str(public_key.as_str()).unwrap();
+            assert!(self.owner_keys.contains(&key), "That key is not an owner");
+            assert!(self.owner_keys.len() as u64 > self.quorum, "Removing that key would leave fewer keys than the quorum");
+            self.owner_keys.retain(|k| k != &key);
+        }
+
+        pub fn set_quorum(&mut self, quorum: u64, account_auth: BucketRef) -> () {
+            self.check_quorum(account_auth);
+
+            assert!(quorum > 0 && quorum as usize <= self.owner_keys.len(), "The quorum must be between 1 and the number of keys");
+            self.quorum = quorum;
+        }
+
+        // withdrawals of more than `threshold` need a quorum, None requires it for every withdrawal
+        pub fn set_multisig_threshold(
+            &mut self,
+            resource_address: Address,
+            threshold: Option<Decimal>,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_quorum(account_auth);
+
+            let mut slot = self.slot(resource_address);
+            slot.multisig_threshold = threshold;
+            self.slots.insert(resource_address, slot);
+        }
+
+        pub fn set_guardians(
+            &mut self,
+            guardian_keys: Vec<String>,
+            guardian_quorum: u64,
+            recovery_delay: u64,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_quorum(account_auth);
+
+            let guardians: Vec<EcdsaPublicKey> = guardian_keys.iter().map(|key| EcdsaPublicKey::from_str(key.as_str()).unwrap()).collect();
+            // no guardians with a quorum of 0 turns social recovery off
+            if guardians.is_empty() {
+                assert!(guardian_quorum == 0, "The guardian quorum must be 0 without guardians");
+            }else{
+                assert!(guardian_quorum > 0 && guardian_quorum as usize <= guardians.len(), "The guardian quorum must be between 1 and the number of guardians");
+            }
+            assert!(self.recovery.is_none(), "Cannot change guardians during a recovery");
+            self.guardians = guardians;
+            self.guardian_quorum = guardian_quorum;
+            self.recovery_delay = recovery_delay;
+        }
+
+        // guardians propose replacing a lost owner key, counting the signing guardians as approvals
+        pub fn start_recovery(&mut self, old_key: String, new_key: String, guardian_auth: BucketRef) -> () {
+            let approvals = self.signing_guardians(guardian_auth);
+            assert!(!approvals.is_empty(), "Only guardians can start a recovery");
+            assert!(self.recovery.is_none(), "A recovery is already in progress");
+
+            let old_key = EcdsaPublicKey::from_str(old_key.as_str()).unwrap();
+            let new_key = EcdsaPublicKey::from_str(new_key.as_str()).unwrap();
+            assert!(self.owner_keys.contains(&old_key), "That key is not an owner");
+            assert!(!self.owner_keys.contains(&new_key), "That key is already an owner");
+            self.recovery = Some(Recovery {
+                old_key,
+                new_key,
+                approvals,
+                started_epoch: Context::current_epoch(),
+            });
+        }
+
+        pub fn approve_recovery(&mut self, guardian_auth: BucketRef) -> () {
+            let signed = self.signing_guardians(guardian_auth);
+            assert!(!signed.is_empty(), "Only guardians can approve a recovery");
+            match &mut self.recovery {
+                Some(recovery) => {
+                    for guardian in signed {
+                        if !recovery.approvals.contains(&guardian) {
+                            recovery.approvals.push(guardian);
+                        }
                     }
                 }
+                None => panic!("No recovery is in progress")
+            }
+        }
+
+        // rotates the key once enough guardians approved and the delay has passed, anyone may call this
+        pub fn finish_recovery(&mut self) -> () {
+            let recovery = match self.recovery.take() {
+                Some(recovery) => recovery,
+                None => panic!("No recovery is in progress")
+            };
+            assert!(recovery.approvals.len() as u64 >= self.guardian_quorum, "{} guardian approvals are required", self.guardian_quorum);
+            assert!(Context::current_epoch() >= recovery.started_epoch + self.recovery_delay, "The recovery delay has not passed yet");
+            assert!(self.owner_keys.contains(&recovery.old_key), "The key being recovered is no longer an owner, cancel the recovery");
+            assert!(!self.owner_keys.contains(&recovery.new_key), "The new key is already an owner, cancel the recovery");
+
+            for key in self.owner_keys.iter_mut() {
+                if *key == recovery.old_key {
+                    *key = recovery.new_key.clone();
+                }
+            }
+        }
+
+        pub fn cancel_recovery(&mut self, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            assert!(self.recovery.is_some(), "No recovery is in progress");
+            self.recovery = None;
+        }
+
+        pub fn get_recovery(&self) -> Option<Recovery> {
+            self.recovery.clone()
+        }
+
+        fn signing_guardians(&self, guardian_auth: BucketRef) -> Vec<EcdsaPublicKey> {
+            let signed = if guardian_auth.resource_address() == ECDSA_TOKEN {
+                guardian_auth.get_non_fungible_keys()
+            } else {
+                Vec::new()
+            };
+            guardian_auth.drop();
+            self.guardians.iter().filter(|key| signed.contains(&NonFungibleKey::new(key.to_vec()))).cloned().collect()
+        }
+
+        pub fn get_owners(&self) -> (Vec<EcdsaPublicKey>, u64, Option<Address>) {
+            let owner_badge = match &self.owner_badge {
+                Some((badge, _key)) => Some(*badge),
+                None => None
+            };
+            (self.owner_keys.clone(), self.quorum, owner_badge)
+        }
+
+        // beneficiaries each claim `share` out of the total shares once the owner has been inactive for `inactivity_period` epochs.
+        // turning inheritance off applies immediately, anything else only after the limit increase delay
+        pub fn set_beneficiaries(
+            &mut self,
+            beneficiaries: Vec<(Address, u64)>,
+            inactivity_period: Option<u64>,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_quorum(account_auth);
+            for (i, (badge, share)) in beneficiaries.iter().enumerate() {
+                assert!(*share > 0, "Beneficiary shares must be positive");
+                assert!(*badge != ECDSA_TOKEN, "Beneficiaries are identified by a badge");
+                assert!(beneficiaries.iter().skip(i + 1).all(|(other, _)| other != badge), "Duplicate beneficiary");
+            }
+            match inactivity_period {
+                Some(period) => {
+                    assert!(period > 0, "The inactivity period must be positive");
+                    self.pending_inheritance = Some((beneficiaries, inactivity_period, Context::current_epoch() + self.increase_delay()));
+                }
                 None => {
-                    panic!("Insufficient balance");
+                    self.beneficiaries = beneficiaries;
+                    self.inactivity_period = None;
+                    self.inheritance_round += 1;
+                    self.round_claimed = false;
+                    self.pending_inheritance = None;
                 }
             }
         }
 
+        fn apply_pending_inheritance(&mut self) {
+            if let Some((beneficiaries, inactivity_period, effective_epoch)) = self.pending_inheritance.clone() {
+                if Context::current_epoch() >= effective_epoch {
+                    self.beneficiaries = beneficiaries;
+                    self.inactivity_period = inactivity_period;
+                    self.inheritance_round += 1;
+                    self.round_claimed = false;
+                    self.pending_inheritance = None;
+                }
+            }
+        }
+
+        // the beneficiaries, inactivity period and round with a due change applied
+        fn current_inheritance(&self) -> (Vec<(Address, u64)>, Option<u64>, u64) {
+            match &self.pending_inheritance {
+                Some((beneficiaries, inactivity_period, effective_epoch)) if Context::current_epoch() >= *effective_epoch => {
+                    (beneficiaries.clone(), *inactivity_period, self.inheritance_round + 1)
+                }
+                _ => (self.beneficiaries.clone(), self.inactivity_period, self.inheritance_round)
+            }
+        }
+
+        // resets the inactivity timer without doing anything else
+        pub fn check_in(&mut self, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+        }
+
+        // beneficiaries with their shares, the inactivity period and the last owner activity
+        pub fn get_inheritance(&self) -> (Vec<(Address, u64)>, Option<u64>, u64) {
+            let (beneficiaries, inactivity_period, _round) = self.current_inheritance();
+            (beneficiaries, inactivity_period, self.last_activity)
+        }
+
+        // beneficiaries and inactivity period waiting for the delay, with the epoch they take effect
+        pub fn get_pending_inheritance(&self) -> Option<(Vec<(Address, u64)>, Option<u64>, u64)> {
+            self.pending_inheritance.clone()
+        }
+
+        pub fn has_claimed(&self, beneficiary: Address, resource_address: Address) -> bool {
+            let (_beneficiaries, _inactivity_period, round) = self.current_inheritance();
+            self.claims.get(&(round, beneficiary, resource_address)).is_some()
+        }
+
+        // pays the beneficiary its share of what is left of the tokens at indexes offset..offset + limit,
+        // lender tokens and separate earnings included. tokens already claimed by this beneficiary are skipped
+        pub fn claim_inheritance(&mut self, beneficiary_auth: BucketRef, offset: u64, limit: u64) -> Vec<Bucket> {
+            self.apply_pending_inheritance();
+            let period = match self.inactivity_period {
+                Some(period) => period,
+                None => panic!("Inheritance is not enabled")
+            };
+            assert!(Context::current_epoch() >= self.last_activity + period, "The owner was active in the last {} epochs", period);
+
+            let beneficiary = beneficiary_auth.resource_address();
+            assert!(beneficiary_auth.amount() > dec!(0), "Not authorized");
+            beneficiary_auth.drop();
+            let share = match self.beneficiaries.iter().find(|(badge, _)| *badge == beneficiary) {
+                Some((_, share)) => *share,
+                None => panic!("Not a beneficiary")
+            };
+            let total_shares: u64 = self.beneficiaries.iter().map(|(_, share)| *share).sum();
+
+            let round = self.inheritance_round;
+            self.round_claimed = true;
+            let end = if offset.saturating_add(limit) < self.token_count {offset + limit} else {self.token_count};
+            let mut buckets = Vec::new();
+            for index in offset..end {
+                let address = self.tokens.get(&index).unwrap();
+                // lender tokens are split together with the token they were issued for
+                if self.lender_lookup.get(&address).is_some() || self.claims.get(&(round, beneficiary, address)).is_some() {
+                    continue;
+                }
+                // shares of those who already claimed this token are paid out, so split what is left among the rest
+                let paid_out = self.claimed_shares.get(&(round, address)).unwrap_or(0);
+                let unclaimed = total_shares - paid_out;
+                self.claims.insert((round, beneficiary, address), true);
+                self.claimed_shares.insert((round, address), paid_out + share);
+
+                let slot = self.slot(address);
+                // pooled nfts can only be split by key, so they are taken back out of the pool first
+                if slot.is_using_bank && self.is_non_fungible(address) {
+                    self.unwind_bank(address);
+                }
+                let mut vault = self.local_vault(address);
+                let bucket = if self.is_non_fungible(address) {
+                    let keys = vault.get_non_fungible_keys();
+                    let count = keys.len() as u64 * share / unclaimed;
+                    let mut bucket = Bucket::new(address);
+                    for key in keys.iter().take(count as usize) {
+                        bucket.put(vault.take_non_fungible(key));
+                    }
+                    bucket
+                } else {
+                    // envelopes shrink with the balance so they never hold more than the account
+                    for name in self.envelope_names.get(&address).unwrap_or(Vec::new()) {
+                        let mut envelope = self.envelope(address, &name);
+                        envelope.balance = envelope.balance * Decimal::from(unclaimed - share) / Decimal::from(unclaimed);
+                        self.envelopes.insert((address, name), envelope);
+                    }
+                    vault.take(vault.amount() * Decimal::from(share) / Decimal::from(unclaimed))
+                };
+                if bucket.amount() > dec!(0) {
+                    self.record_history(HistoryKind::Inheritance, address, bucket.amount(), Some(beneficiary));
+                    buckets.push(bucket);
+                }
+                if let Some(lender_address) = slot.bank_token_address {
+                    let mut lenders = self.vaults.get(&lender_address).unwrap();
+                    if lenders.amount() > dec!(0) {
+                        let bucket = lenders.take(lenders.amount() * Decimal::from(share) / Decimal::from(unclaimed));
+                        self.record_history(HistoryKind::Inheritance, lender_address, bucket.amount(), Some(beneficiary));
+                        buckets.push(bucket);
+                    }
+                }
+                if let Some(mut earnings) = self.earnings.get(&address) {
+                    if earnings.amount() > dec!(0) {
+                        let bucket = earnings.take(earnings.amount() * Decimal::from(share) / Decimal::from(unclaimed));
+                        self.record_history(HistoryKind::Inheritance, address, bucket.amount(), Some(beneficiary));
+                        buckets.push(bucket);
+                    }
+                }
+            }
+            buckets
+        }
+
+        /// Withdraws resource from this account.
+        pub fn withdraw(
+            &mut self,
+            amount: Decimal,
+            resource_address: Address,
+            account_auth: BucketRef,
+        ) -> Bucket {
+            self.check_withdrawal(resource_address, amount, account_auth);
+            self.record_history(HistoryKind::Withdrawal, resource_address, amount, None);
+
+            let unwound = self.make_available(resource_address, amount);
+            let withdrawn_tokens = self.local_vault(resource_address).take(amount);
+            self.resume_banking(resource_address, unwound);
+            withdrawn_tokens
+        }
+
         /// Withdraws resource from this account.
         pub fn withdraw_with_auth(
             &mut self,
@@ -110,15 +770,13 @@ blueprint! {
             auth: BucketRef,
             account_auth: BucketRef,
         ) -> Bucket {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
+            self.check_withdrawal(resource_address, amount, account_auth);
+            self.record_history(HistoryKind::Withdrawal, resource_address, amount, None);
 
-            let vault = self.vaults.get(&resource_address);
-            match vault {
-                Some( (mut vault, _bank_token_address, _is_using_bank, _local_storage_is_borrowable, _loan_interest) ) => vault.take_with_auth(amount, auth),
-                None => {
-                    panic!("Insufficient balance");
-                }
-            }
+            let unwound = self.make_available(resource_address, amount);
+            let withdrawn_tokens = self.local_vault(resource_address).take_with_auth(amount, auth);
+            self.resume_banking(resource_address, unwound);
+            withdrawn_tokens
         }
 
         /// Withdraws non-fungibles from this account.
@@ -128,21 +786,16 @@ blueprint! {
             resource_address: Address,
             account_auth: BucketRef,
         ) -> Bucket {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
-
-            let vault = self.vaults.get(&resource_address);
-            match vault {
-                Some((vault, _bank_token_address, _is_using_bank, _local_storage_is_borrowable, _loan_interest)) => {
-                    let mut bucket = Bucket::new(resource_address);
-                    for key in keys {
-                        bucket.put(vault.take_non_fungible(&key));
-                    }
-                    bucket
-                }
-                None => {
-                    panic!("Insufficient balance");
-                }
+            self.check_withdrawal(resource_address, Decimal::from(keys.len() as u64), account_auth);
+            self.record_history(HistoryKind::Withdrawal, resource_address, Decimal::from(keys.len() as u64), None);
+
+            self.redeem_non_fungibles(resource_address, &keys);
+            let vault = self.local_vault(resource_address);
+            let mut bucket = Bucket::new(resource_address);
+            for key in keys {
+                bucket.put(vault.take_non_fungible(&key));
             }
+            bucket
         }
 
         /// Withdraws non-fungibles from this account.
@@ -153,87 +806,970 @@ blueprint! {
             auth: BucketRef,
             account_auth: BucketRef,
         ) -> Bucket {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
-
-            let vault = self.vaults.get(&resource_address);
-            match vault {
-                Some((vault, _bank_token_address, _is_using_bank, _local_storage_is_borrowable, _loan_interest)) => {
-                    let mut bucket = Bucket::new(resource_address);
-                    for key in keys {
-                        bucket.put(vault.take_non_fungible_with_auth(&key, auth.clone()));
+            self.check_withdrawal(resource_address, Decimal::from(keys.len() as u64), account_auth);
+            self.record_history(HistoryKind::Withdrawal, resource_address, Decimal::from(keys.len() as u64), None);
+
+            self.redeem_non_fungibles(resource_address, &keys);
+            let vault = self.local_vault(resource_address);
+            let mut bucket = Bucket::new(resource_address);
+            for key in keys {
+                bucket.put(vault.take_non_fungible_with_auth(&key, auth.clone()));
+            }
+            bucket
+        }
+
+        // lets the holder of the spender badge pull up to `amount` of a token, a component spender presents its own badge
+        pub fn approve(
+            &mut self,
+            spender: Address,
+            resource_address: Address,
+            amount: Decimal,
+            per_epoch: bool,
+            account_auth: BucketRef,
+        ) -> () {
+            // an allowance can be pulled without the owners, so it needs the signatures a withdrawal of that size would
+            self.check_signers(resource_address, amount, account_auth);
+
+            assert!(spender != ECDSA_TOKEN, "Spenders must be identified by a badge");
+            assert!(amount >= dec!(0), "The allowance cannot be negative");
+            self.slot(resource_address);
+            self.assert_not_lender_token(resource_address);
+            self.allowances.insert((spender, resource_address), Allowance {
+                amount,
+                per_epoch,
+                spent: dec!(0),
+                epoch: Context::current_epoch(),
+            });
+        }
+
+        pub fn revoke(
+            &mut self,
+            spender: Address,
+            resource_address: Address,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            self.allowances.insert((spender, resource_address), Allowance {
+                amount: dec!(0),
+                per_epoch: false,
+                spent: dec!(0),
+                epoch: Context::current_epoch(),
+            });
+        }
+
+        // what the spender can still pull right now
+        pub fn get_allowance(&self, spender: Address, resource_address: Address) -> Decimal {
+            match self.current_allowance(spender, resource_address) {
+                Some(allowance) if allowance.amount > allowance.spent => allowance.amount - allowance.spent,
+                _ => dec!(0)
+            }
+        }
+
+        // withdraws within an allowance, unwinding from the bank if the token is banked
+        pub fn pull(
+            &mut self,
+            amount: Decimal,
+            resource_address: Address,
+            spender_auth: BucketRef,
+        ) -> Bucket {
+            let spender = spender_auth.resource_address();
+            assert!(spender_auth.amount() > dec!(0), "Not authorized");
+            spender_auth.drop();
+
+            let mut allowance = match self.current_allowance(spender, resource_address) {
+                Some(allowance) => allowance,
+                None => panic!("No allowance for this spender")
+            };
+            assert!(allowance.spent + amount <= allowance.amount, "Allowance exceeded");
+            allowance.spent = allowance.spent + amount;
+            self.allowances.insert((spender, resource_address), allowance);
+            self.record_withdrawal(resource_address, amount);
+            self.record_history(HistoryKind::Pull, resource_address, amount, Some(spender));
+
+            let unwound = self.make_available(resource_address, amount);
+            let withdrawn_tokens = self.local_vault(resource_address).take(amount);
+            self.resume_banking(resource_address, unwound);
+            withdrawn_tokens
+        }
+
+        // the allowance with its spending reset if it is per epoch and a new epoch started
+        fn current_allowance(&self, spender: Address, resource_address: Address) -> Option<Allowance> {
+            match self.allowances.get(&(spender, resource_address)) {
+                Some(mut allowance) => {
+                    let epoch = Context::current_epoch();
+                    if allowance.per_epoch && allowance.epoch != epoch {
+                        allowance.epoch = epoch;
+                        allowance.spent = dec!(0);
                     }
-                    bucket
+                    Some(allowance)
                 }
-                None => {
-                    panic!("Insufficient balance")
+                None => None
+            }
+        }
+
+        // sends `amount` to the recipient every `interval` epochs starting at `first_epoch`, returns the payment id
+        pub fn schedule_payment(
+            &mut self,
+            recipient: Address,
+            resource_address: Address,
+            amount: Decimal,
+            interval: u64,
+            first_epoch: u64,
+            end_epoch: Option<u64>,
+            account_auth: BucketRef,
+        ) -> u64 {
+            self.check_signers(resource_address, amount, account_auth);
+
+            assert!(interval > 0, "The interval must be at least one epoch");
+            assert!(first_epoch >= Context::current_epoch(), "The first payment can't be in the past");
+            assert!(amount > dec!(0), "The amount must be positive");
+            self.slot(resource_address);
+            self.assert_not_lender_token(resource_address);
+            let id = self.next_payment_id;
+            self.next_payment_id += 1;
+            self.payments.push(ScheduledPayment {
+                id,
+                recipient,
+                resource_address,
+                amount,
+                interval,
+                next_epoch: first_epoch,
+                end_epoch,
+            });
+            id
+        }
+
+        pub fn cancel_payment(&mut self, id: u64, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            assert!(self.payments.iter().any(|payment| payment.id == id), "No payment with that id is scheduled");
+            self.payments.retain(|payment| payment.id != id);
+        }
+
+        pub fn get_scheduled_payments(&self) -> Vec<ScheduledPayment> {
+            self.payments.clone()
+        }
+
+        // pays every due payment once, anyone may call this. payments that the balance or withdrawal limit
+        // can't cover stay due for a later call. a recipient that panics reverts the whole call, keepers
+        // then pay the others one by one with execute_payment
+        pub fn execute_due_payments(&mut self) -> u64 {
+            let mut paid = 0;
+            for id in self.get_due_payments() {
+                if self.pay_if_due(id) {
+                    paid += 1;
                 }
             }
+            paid
+        }
+
+        // pays one payment if it is due, anyone may call this
+        pub fn execute_payment(&mut self, id: u64) -> bool {
+            assert!(self.payments.iter().any(|payment| payment.id == id), "No payment with that id is scheduled");
+            self.pay_if_due(id)
+        }
+
+        // ids of the payments execute_due_payments would try to pay now
+        pub fn get_due_payments(&self) -> Vec<u64> {
+            let epoch = Context::current_epoch();
+            self.payments.iter()
+                .filter(|payment| payment.next_epoch <= epoch && !Self::is_finished(payment))
+                .map(|payment| payment.id)
+                .collect()
+        }
+
+        // the payment is advanced, or dropped once finished, before the recipient is called,
+        // so a recipient calling back in finds it already paid for this epoch
+        fn pay_if_due(&mut self, id: u64) -> bool {
+            let epoch = Context::current_epoch();
+            let index = match self.payments.iter().position(|payment| payment.id == id) {
+                Some(index) => index,
+                None => return false
+            };
+            let payment = self.payments[index].clone();
+            if payment.next_epoch > epoch || Self::is_finished(&payment) || !self.can_withdraw(payment.resource_address, payment.amount) {
+                return false;
+            }
+            // missed intervals are skipped rather than paid in one burst
+            self.payments[index].next_epoch += payment.interval * ((epoch - payment.next_epoch) / payment.interval + 1);
+            self.payments.retain(|payment| !Self::is_finished(payment));
+
+            self.record_withdrawal(payment.resource_address, payment.amount);
+            self.record_history(HistoryKind::ScheduledPayment, payment.resource_address, payment.amount, Some(payment.recipient));
+            let unwound = self.make_available(payment.resource_address, payment.amount);
+            let bucket = self.local_vault(payment.resource_address).take(payment.amount);
+            self.resume_banking(payment.resource_address, unwound);
+            Component::from(payment.recipient).call::<()>("deposit", vec![scrypto_encode(&bucket)]);
+            true
         }
 
-        // makes a token auto-lended
+        fn is_finished(payment: &ScheduledPayment) -> bool {
+            match payment.end_epoch {
+                Some(end_epoch) => payment.next_epoch > end_epoch,
+                None => false
+            }
+        }
+
+        // whether the balance, banked or not, and this epoch's withdrawal limit cover the amount
+        fn can_withdraw(&self, resource_address: Address, amount: Decimal) -> bool {
+            let slot = self.current_limit(resource_address);
+            let within_limit = match slot.daily_limit {
+                Some(limit) => slot.withdrawn_this_epoch + amount <= limit,
+                None => true
+            };
+            let balance_after = self.total_balance(resource_address) - amount;
+            within_limit && self.unallocated(resource_address) - self.locked_amount(resource_address, balance_after) >= amount
+        }
+
+        fn total_balance(&self, resource_address: Address) -> Decimal {
+            self.local_vault(resource_address).amount() + self.banked_value(resource_address)
+        }
+
+        // locks `amount` until `unlock_epoch` or until the total balance reaches `target_balance`, whichever comes first
+        pub fn create_goal(
+            &mut self,
+            resource_address: Address,
+            amount: Decimal,
+            unlock_epoch: Option<u64>,
+            target_balance: Option<Decimal>,
+            account_auth: BucketRef,
+        ) -> u64 {
+            self.check_owner(account_auth);
+
+            assert!(unlock_epoch.is_some() || target_balance.is_some(), "A goal needs an unlock epoch or a target balance");
+            assert!(amount > dec!(0), "The amount must be positive");
+            self.slot(resource_address);
+            // envelope withdrawals skip goals, so a goal can only lock funds outside envelopes
+            self.prune_goals();
+            assert!(self.goals.len() < MAX_GOALS, "At most {} savings goals can be open at once", MAX_GOALS);
+            let balance = self.total_balance(resource_address);
+            let free = self.unallocated(resource_address) - self.locked_amount(resource_address, balance);
+            assert!(amount <= free, "Only {} of this token is outside envelopes and savings goals", free);
+            let id = self.next_goal_id;
+            self.next_goal_id += 1;
+            self.goals.push(SavingsGoal {
+                id,
+                resource_address,
+                amount,
+                unlock_epoch,
+                target_balance,
+                reached: false,
+            });
+            id
+        }
+
+        pub fn get_goals(&self) -> Vec<SavingsGoal> {
+            self.goals.iter().map(|goal| {
+                let mut goal = goal.clone();
+                goal.reached = self.is_goal_reached(&goal, self.total_balance(goal.resource_address));
+                goal
+            }).collect()
+        }
+
+        // goals past their unlock epoch stay unlocked for good, so they are dropped
+        fn prune_goals(&mut self) {
+            let epoch = Context::current_epoch();
+            self.goals.retain(|goal| match goal.unlock_epoch {
+                Some(unlock_epoch) => epoch < unlock_epoch,
+                None => true
+            });
+        }
+
+        // a target balance only unlocks a goal while the balance, after the withdrawal being checked, stays at it
+        fn is_goal_reached(&self, goal: &SavingsGoal, balance: Decimal) -> bool {
+            let epoch_reached = match goal.unlock_epoch {
+                Some(unlock_epoch) => Context::current_epoch() >= unlock_epoch,
+                None => false
+            };
+            let balance_reached = match goal.target_balance {
+                Some(target_balance) => balance >= target_balance,
+                None => false
+            };
+            epoch_reached || balance_reached
+        }
+
+        // sum of goals of this token that are still locked at the given total balance
+        fn locked_amount(&self, resource_address: Address, balance: Decimal) -> Decimal {
+            self.goals.iter()
+                .filter(|goal| goal.resource_address == resource_address && !self.is_goal_reached(goal, balance))
+                .fold(dec!(0), |sum, goal| sum + goal.amount)
+        }
+
+        pub fn create_envelope(
+            &mut self,
+            resource_address: Address,
+            name: String,
+            is_banked: bool,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            self.slot(resource_address);
+            assert!(!self.is_non_fungible(resource_address), "Envelopes only hold fungible tokens");
+            let mut names = self.envelope_names.get(&resource_address).unwrap_or(Vec::new());
+            assert!(!names.contains(&name), "Envelope {} already exists", name);
+            names.push(name.clone());
+            self.envelope_names.insert(resource_address, names);
+            self.envelopes.insert((resource_address, name.clone()), Envelope {
+                name,
+                resource_address,
+                balance: dec!(0),
+                is_banked,
+                limit: None,
+                withdrawn_this_epoch: dec!(0),
+                limit_epoch: 0,
+                unlock_epoch: None,
+                target_balance: None,
+                reached: true,
+            });
+            self.sync_envelope_buffer(resource_address);
+        }
+
+        // releases the envelope's balance back to the unallocated balance
+        pub fn delete_envelope(&mut self, resource_address: Address, name: String, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            let envelope = self.envelope(resource_address, &name);
+            assert!(envelope.balance == dec!(0) || self.is_envelope_unlocked(&envelope), "Envelope {} is locked by its goal", name);
+            let mut names = self.envelope_names.get(&resource_address).unwrap();
+            names.retain(|other| *other != name);
+            self.envelope_names.insert(resource_address, names);
+            self.sync_envelope_buffer(resource_address);
+        }
+
+        // moves unallocated balance into an envelope
+        pub fn allocate(&mut self, resource_address: Address, name: String, amount: Decimal, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            let mut envelope = self.envelope(resource_address, &name);
+            assert!(amount > dec!(0), "The amount must be positive");
+            let balance = self.total_balance(resource_address);
+            let free = self.unallocated(resource_address) - self.locked_amount(resource_address, balance);
+            assert!(free >= amount, "Only {} of this token is free to allocate", free);
+            envelope.balance = envelope.balance + amount;
+            self.envelopes.insert((resource_address, name), envelope);
+            self.sync_envelope_buffer(resource_address);
+        }
+
+        // moves an envelope's balance back to the unallocated balance
+        pub fn release(&mut self, resource_address: Address, name: String, amount: Decimal, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            let mut envelope = self.unlocked_envelope(resource_address, &name);
+            assert!(envelope.balance >= amount, "Envelope {} only holds {}", name, envelope.balance);
+            envelope.balance = envelope.balance - amount;
+            self.envelopes.insert((resource_address, name), envelope);
+            self.sync_envelope_buffer(resource_address);
+        }
+
+        pub fn transfer_between_envelopes(
+            &mut self,
+            resource_address: Address,
+            from: String,
+            to: String,
+            amount: Decimal,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            assert!(from != to, "Can't transfer an envelope to itself");
+            let mut source = self.unlocked_envelope(resource_address, &from);
+            let mut destination = self.envelope(resource_address, &to);
+            assert!(source.balance >= amount, "Envelope {} only holds {}", from, source.balance);
+            source.balance = source.balance - amount;
+            destination.balance = destination.balance + amount;
+            self.envelopes.insert((resource_address, from), source);
+            self.envelopes.insert((resource_address, to), destination);
+            self.sync_envelope_buffer(resource_address);
+        }
+
+        // deposits straight into an envelope, anyone can fund one
+        pub fn deposit_to_envelope(&mut self, bucket: Bucket, name: String) {
+            let resource_address = bucket.resource_address();
+            let mut envelope = self.envelope(resource_address, &name);
+            envelope.balance = envelope.balance + bucket.amount();
+            self.deposit(bucket);
+            self.envelopes.insert((resource_address, name), envelope);
+            self.sync_envelope_buffer(resource_address);
+        }
+
+        // withdrawals from an envelope skip the account's savings goals but count against the token's and the envelope's limits
+        pub fn withdraw_from_envelope(
+            &mut self,
+            resource_address: Address,
+            name: String,
+            amount: Decimal,
+            account_auth: BucketRef,
+        ) -> Bucket {
+            self.check_signers(resource_address, amount, account_auth);
+
+            let mut envelope = self.unlocked_envelope(resource_address, &name);
+            assert!(envelope.balance >= amount, "Envelope {} only holds {}", name, envelope.balance);
+            let epoch = Context::current_epoch();
+            if envelope.limit_epoch != epoch {
+                envelope.limit_epoch = epoch;
+                envelope.withdrawn_this_epoch = dec!(0);
+            }
+            if let Some(limit) = envelope.limit {
+                assert!(envelope.withdrawn_this_epoch + amount <= limit, "Envelope {} has a limit of {} per epoch", name, limit);
+            }
+            envelope.withdrawn_this_epoch = envelope.withdrawn_this_epoch + amount;
+            envelope.balance = envelope.balance - amount;
+            self.envelopes.insert((resource_address, name), envelope);
+            self.count_against_limit(resource_address, amount);
+            self.record_history(HistoryKind::Withdrawal, resource_address, amount, None);
+
+            let unwound = self.make_available(resource_address, amount);
+            let withdrawn_tokens = self.local_vault(resource_address).take(amount);
+            self.resume_banking(resource_address, unwound);
+            withdrawn_tokens
+        }
+
+        // unbanked envelopes are kept in the local vault on top of the token's buffer target
+        pub fn set_envelope_banking(&mut self, resource_address: Address, name: String, is_banked: bool, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            let mut envelope = self.envelope(resource_address, &name);
+            envelope.is_banked = is_banked;
+            self.envelopes.insert((resource_address, name), envelope);
+            self.sync_envelope_buffer(resource_address);
+        }
+
+        pub fn set_envelope_limit(&mut self, resource_address: Address, name: String, limit: Option<Decimal>, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            let mut envelope = self.envelope(resource_address, &name);
+            envelope.limit = limit;
+            self.envelopes.insert((resource_address, name), envelope);
+        }
+
+        // locks the envelope until `unlock_epoch` or until its balance reaches `target_balance`, whichever comes first
+        pub fn set_envelope_goal(
+            &mut self,
+            resource_address: Address,
+            name: String,
+            unlock_epoch: Option<u64>,
+            target_balance: Option<Decimal>,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            assert!(unlock_epoch.is_some() || target_balance.is_some(), "A goal needs an unlock epoch or a target balance");
+            let mut envelope = self.unlocked_envelope(resource_address, &name);
+            envelope.unlock_epoch = unlock_epoch;
+            envelope.target_balance = target_balance;
+            envelope.reached = false;
+            self.envelopes.insert((resource_address, name), envelope);
+        }
+
+        pub fn get_envelopes(&self, resource_address: Address) -> Vec<Envelope> {
+            self.envelope_names.get(&resource_address).unwrap_or(Vec::new()).iter()
+                .map(|name| {
+                    let mut envelope = self.envelope(resource_address, name);
+                    envelope.reached = self.is_envelope_unlocked(&envelope);
+                    envelope
+                })
+                .collect()
+        }
+
+        fn envelope(&self, resource_address: Address, name: &String) -> Envelope {
+            let exists = self.envelope_names.get(&resource_address).map_or(false, |names| names.contains(name));
+            assert!(exists, "No envelope named {}", name);
+            self.envelopes.get(&(resource_address, name.clone())).unwrap()
+        }
+
+        // the envelope, refusing if its goal still locks it, reached goals stay unlocked
+        fn unlocked_envelope(&self, resource_address: Address, name: &String) -> Envelope {
+            let mut envelope = self.envelope(resource_address, name);
+            assert!(self.is_envelope_unlocked(&envelope), "Envelope {} is locked by its goal", name);
+            envelope.reached = true;
+            envelope
+        }
+
+        fn is_envelope_unlocked(&self, envelope: &Envelope) -> bool {
+            let epoch_reached = match envelope.unlock_epoch {
+                Some(unlock_epoch) => Context::current_epoch() >= unlock_epoch,
+                None => false
+            };
+            let balance_reached = match envelope.target_balance {
+                Some(target_balance) => envelope.balance >= target_balance,
+                None => false
+            };
+            envelope.reached || epoch_reached || balance_reached
+        }
+
+        fn allocated(&self, resource_address: Address, only_unbanked: bool) -> Decimal {
+            self.envelope_names.get(&resource_address).unwrap_or(Vec::new()).iter()
+                .map(|name| self.envelope(resource_address, name))
+                .filter(|envelope| !only_unbanked || !envelope.is_banked)
+                .fold(dec!(0), |sum, envelope| sum + envelope.balance)
+        }
+
+        fn unallocated(&self, resource_address: Address) -> Decimal {
+            self.total_balance(resource_address) - self.allocated(resource_address, false)
+        }
+
+        // moves funds between the local vault and the bank after the unbanked envelopes changed
+        fn sync_envelope_buffer(&mut self, resource_address: Address) {
+            if self.slot(resource_address).is_using_bank {
+                self.refill_buffer(resource_address);
+                self.bank_excess(resource_address);
+            }
+        }
+
+        // small withdrawals are served from the liquid buffer. once it runs dry only enough lender tokens are
+        // redeemed to cover the withdrawal and refill the buffer, returns whether the bank had to be unwound
+        fn make_available(&mut self, resource_address: Address, amount: Decimal) -> bool {
+            let slot = self.slot(resource_address);
+            let local = self.local_vault(resource_address).amount();
+            if !slot.is_using_bank || local >= amount {
+                return false;
+            }
+            let total = local + self.banked_value(resource_address);
+            let remaining = if total > amount {total - amount} else {dec!(0)};
+            let buffer = self.buffer_target_amount(resource_address, remaining);
+            self.redeem_underlying(resource_address, amount - local + buffer);
+
+            // rounding in the exchange rate can leave the vault a hair short
+            if self.local_vault(resource_address).amount() < amount {
+                self.unwind_bank(resource_address);
+                return true;
+            }
+            false
+        }
+
+        // redeems the pool receipts of whichever of the keys are banked, so all of them end up in the local vault
+        fn redeem_non_fungibles(&mut self, resource_address: Address, keys: &BTreeSet<NonFungibleKey>) {
+            let local_keys = self.local_vault(resource_address).get_non_fungible_keys();
+            let banked: Vec<&NonFungibleKey> = keys.iter().filter(|key| !local_keys.contains(key)).collect();
+            if banked.is_empty() {
+                return;
+            }
+            let slot = self.slot(resource_address);
+            assert!(slot.is_using_bank, "Those nfts are not in the account");
+            let mut receipt_vault = self.lender_vault(&slot);
+            let receipt_keys = receipt_vault.get_non_fungible_keys();
+            assert!(banked.iter().all(|key| receipt_keys.contains(key)), "Those nfts are not in the account");
+
+            let mut receipts = Bucket::new(receipt_vault.resource_address());
+            for key in banked {
+                receipts.put(receipt_vault.take_non_fungible(key));
+            }
+            let nfts = self.pool_withdraw(slot.bank.unwrap(), receipts);
+            self.local_vault(resource_address).put(nfts);
+        }
+
+        // puts a banked token back to its configured split after a withdrawal
+        fn resume_banking(&mut self, resource_address: Address, unwound: bool) {
+            let slot = self.slot(resource_address);
+            if !slot.is_using_bank {
+                return;
+            }
+            if unwound {
+                self.bank_excess(resource_address);
+            }else if slot.rebalance_threshold.is_some() {
+                self.rebalance_if_drifted(resource_address, true);
+            }
+        }
+
+        // makes a token auto-lended, banked nfts can still be withdrawn by key through their pool receipts
         pub fn bank_token(
             &mut self,
             resource_address: Address,
             account_auth: BucketRef,
         ) -> () {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
-
-            let vault = self.vaults.get(&resource_address);
-            match vault {
-                Some((mut vault, _bank_token_address, is_using_bank, local_storage_is_borrowable, loan_interest)) => {
-                    assert!(!is_using_bank, "already using Bank for this token");
-                    let args = vec![
-                        scrypto_encode(&vault.take(vault.amount()))
-                    ];
-
-                    let lended_tokens: Bucket = Component::from(self.bank).call::<Bucket>("deposit", args).into();
-                    self.vaults.insert(resource_address, (vault, Some(lended_tokens.resource_address()), true, local_storage_is_borrowable, loan_interest) );
-                    self.deposit(lended_tokens);
-                }
-                None => {
-                    panic!("Insufficient balance")
-                }
+            self.check_quorum(account_auth);
+
+            let slot = self.slot(resource_address);
+            assert!(!slot.is_using_bank, "already using Bank for this token");
+            if self.is_non_fungible(resource_address) && slot.bank.is_none() {
+                panic!("Non-fungible tokens cannot be banked at a Bank, it only issues fungible lender tokens. Choose an NftPool with set_bank_for_token and finish_bank_change first");
+            }
+            let bank = match slot.bank {
+                Some(bank) => bank,
+                None => self.bank
+            };
+            self.record_history(HistoryKind::Bank, resource_address, self.total_balance(resource_address), Some(bank));
+            self.start_banking(resource_address, bank);
+        }
+
+        // chooses the bank a token is banked at. a bank is handed everything above the buffer, so choosing one
+        // takes a quorum and only takes effect after the limit increase delay, with finish_bank_change
+        pub fn set_bank_for_token(
+            &mut self,
+            resource_address: Address,
+            bank: Address,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_quorum(account_auth);
+
+            let mut slot = self.slot(resource_address);
+            slot.pending_bank = Some((bank, Context::current_epoch() + self.increase_delay()));
+            self.slots.insert(resource_address, slot);
+        }
+
+        // switches to the chosen bank once the delay has passed, moving any banked funds over, anyone may call this
+        pub fn finish_bank_change(&mut self, resource_address: Address) -> () {
+            let mut slot = self.slot(resource_address);
+            let (bank, effective_epoch) = match slot.pending_bank {
+                Some(pending) => pending,
+                None => panic!("No bank change is pending")
+            };
+            assert!(Context::current_epoch() >= effective_epoch, "The bank change can be finished at epoch {}", effective_epoch);
+            slot.pending_bank = None;
+            let old_bank = slot.bank;
+            let is_using_bank = slot.is_using_bank;
+            if !is_using_bank {
+                slot.bank = Some(bank);
+            }
+            self.slots.insert(resource_address, slot);
+
+            if is_using_bank {
+                self.unwind_bank(resource_address);
+                let total = self.total_balance(resource_address);
+                self.record_history(HistoryKind::Unbank, resource_address, total, old_bank);
+                self.record_history(HistoryKind::Bank, resource_address, total, Some(bank));
+                self.start_banking(resource_address, bank);
             }
         }
 
+        pub fn cancel_bank_change(&mut self, resource_address: Address, account_auth: BucketRef) -> () {
+            self.check_owner(account_auth);
+
+            let mut slot = self.slot(resource_address);
+            assert!(slot.pending_bank.is_some(), "No bank change is pending");
+            slot.pending_bank = None;
+            self.slots.insert(resource_address, slot);
+        }
+
+        // deposits everything above the buffer target at the given bank and marks the token as banked there
+        fn start_banking(&mut self, resource_address: Address, bank: Address) {
+            let mut slot = self.slot(resource_address);
+            let mut vault = self.local_vault(resource_address);
+            let buffer = self.buffer_target_amount(resource_address, vault.amount());
+            let banked_amount = if vault.amount() > buffer {vault.amount() - buffer} else {dec!(0)};
+            let lended_tokens = self.bank_deposit(bank, vault.take(banked_amount));
+            slot.bank_token_address = Some(lended_tokens.resource_address());
+            slot.bank = Some(bank);
+            self.lender_lookup.insert(lended_tokens.resource_address(), resource_address);
+            slot.is_using_bank = true;
+            self.slots.insert(resource_address, slot);
+            self.store(lended_tokens);
+        }
+
         // makes a token no longer auto-lended
         pub fn unbank_token(
             &mut self,
             resource_address: Address,
             account_auth: BucketRef,
         ) -> () {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
+            self.check_owner(account_auth);
 
-            let vault = self.vaults.get(&resource_address);
-            match vault {
-                Some((mut vault, bank_token_address, is_using_bank, local_storage_is_borrowable, loan_interest)) => {
-                    assert!(is_using_bank, "Bank is not currently used for this token");
+            let mut slot = self.slot(resource_address);
+            assert!(slot.is_using_bank, "Bank is not currently used for this token");
 
-                    let (mut v, _bta, _iub, _lsb, _li) = self.vaults.get(&bank_token_address.unwrap()).unwrap();
-                    let args = vec![
-                        scrypto_encode(&v.take(v.amount()))
-                    ];
+            self.unwind_bank(resource_address);
+            let bank = slot.bank;
+            slot.is_using_bank = false;
+            self.slots.insert(resource_address, slot);
+            self.record_history(HistoryKind::Unbank, resource_address, self.total_balance(resource_address), bank);
+        }
 
-                    let base_tokens: Bucket = Component::from(self.bank).call::<Bucket>("withdraw", args).into();
-                    vault.put(base_tokens);
-                    self.vaults.insert(resource_address, (vault, bank_token_address, false, local_storage_is_borrowable, loan_interest) );
-                }
-                None => {
-                    panic!("Insufficient balance")
+        // sets how much of a banked token is kept liquid in the local vault
+        pub fn set_buffer_target(
+            &mut self,
+            resource_address: Address,
+            buffer_target: BufferTarget,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            let target_amount = match &buffer_target {
+                BufferTarget::Percent(percent) => {
+                    assert!(*percent >= dec!(0) && *percent <= dec!(100), "The buffer percentage must be between 0 and 100");
+                    *percent
                 }
+                BufferTarget::Fixed(amount) => *amount
+            };
+            assert!(target_amount >= dec!(0), "The buffer target cannot be negative");
+            let mut slot = self.slot(resource_address);
+            slot.buffer_target = Some(buffer_target);
+            self.slots.insert(resource_address, slot);
+        }
+
+        // sets how far the local buffer may drift from its target before deposits, withdrawals or rebalance move funds
+        pub fn set_rebalance_threshold(
+            &mut self,
+            resource_address: Address,
+            threshold: Decimal,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            assert!(threshold >= dec!(0) && threshold <= dec!(100), "The rebalance threshold must be a percentage between 0 and 100");
+            let mut slot = self.slot(resource_address);
+            slot.rebalance_threshold = Some(threshold);
+            self.slots.insert(resource_address, slot);
+        }
+
+        // moves funds between the local vault and the bank to restore the buffer target, anyone may call this
+        pub fn rebalance(&mut self, resource_address: Address) -> () {
+            assert!(self.slot(resource_address).is_using_bank, "Bank is not currently used for this token");
+            self.rebalance_if_drifted(resource_address, true);
+        }
+
+        // banks the excess, or refills the buffer when `refill` is set, if the buffer is further than the threshold from its target
+        fn rebalance_if_drifted(&mut self, resource_address: Address, refill: bool) {
+            let vault = self.local_vault(resource_address);
+            let total = vault.amount() + self.banked_value(resource_address);
+            let target = self.buffer_target_amount(resource_address, total);
+            let threshold = match self.slot(resource_address).rebalance_threshold {
+                Some(threshold) => total * threshold / 100,
+                None => dec!(0)
+            };
+
+            if vault.amount() > target + threshold {
+                self.bank_excess(resource_address);
+            }else if refill && vault.amount() + threshold < target {
+                self.refill_buffer(resource_address);
+            }
+        }
+
+        // redeems just enough lender tokens to bring the local vault back up to its buffer target
+        fn refill_buffer(&mut self, resource_address: Address) {
+            let vault = self.local_vault(resource_address);
+            let total = vault.amount() + self.banked_value(resource_address);
+            let shortfall = self.buffer_target_amount(resource_address, total) - vault.amount();
+            if shortfall > dec!(0) {
+                self.redeem_underlying(resource_address, shortfall);
             }
         }
 
+        // redeems lender tokens worth `amount` of a banked token into its local vault, or all of them if they are worth less
+        fn redeem_underlying(&mut self, resource_address: Address, amount: Decimal) {
+            let slot = self.slot(resource_address);
+            let mut v = self.lender_vault(&slot);
+            let rate: Decimal = Component::from(slot.bank.unwrap()).call::<Decimal>("get_exchange_rate", vec![scrypto_encode(&resource_address)]);
+            let lender_amount = amount / rate;
+            let lenders = v.take(if lender_amount > v.amount() {v.amount()} else {lender_amount});
+            let base_tokens = self.bank_withdraw(resource_address, slot.bank.unwrap(), lenders);
+            self.local_vault(resource_address).put(base_tokens);
+        }
+
+        // local amount the buffer target asks for, given the token's total balance
+        fn buffer_target_amount(&self, resource_address: Address, total: Decimal) -> Decimal {
+            // pooled nfts are banked whole
+            if self.is_non_fungible(resource_address) {
+                return dec!(0);
+            }
+            // unbanked envelopes stay local, the buffer target applies to the rest
+            let unbanked = self.allocated(resource_address, true);
+            let rest = if total > unbanked {total - unbanked} else {dec!(0)};
+            unbanked + match self.slot(resource_address).buffer_target {
+                Some(BufferTarget::Percent(percent)) => rest * percent / 100,
+                Some(BufferTarget::Fixed(amount)) => amount,
+                None => dec!(0)
+            }
+        }
+
+        // underlying value of the lender tokens held for a banked token
+        fn banked_value(&self, resource_address: Address) -> Decimal {
+            let slot = self.slot(resource_address);
+            if !slot.is_using_bank {
+                return dec!(0);
+            }
+            let rate: Decimal = Component::from(slot.bank.unwrap()).call::<Decimal>("get_exchange_rate", vec![scrypto_encode(&resource_address)]);
+            self.lender_vault(&slot).amount() * rate
+        }
+
+        // redeems every lender token of a banked token back into its local vault
+        fn unwind_bank(&mut self, resource_address: Address) {
+            let slot = self.slot(resource_address);
+            let mut v = self.lender_vault(&slot);
+            let base_tokens = self.bank_withdraw(resource_address, slot.bank.unwrap(), v.take(v.amount()));
+            self.local_vault(resource_address).put(base_tokens);
+        }
+
+        // banks whatever the local vault holds above its buffer target
+        fn bank_excess(&mut self, resource_address: Address) {
+            let slot = self.slot(resource_address);
+            let mut vault = self.local_vault(resource_address);
+            let total = vault.amount() + self.banked_value(resource_address);
+            let buffer = self.buffer_target_amount(resource_address, total);
+            if vault.amount() > buffer {
+                let lended_tokens = self.bank_deposit(slot.bank.unwrap(), vault.take(vault.amount() - buffer));
+                self.lender_vault(&slot).put(lended_tokens);
+            }
+        }
+
+        // deposits at a Bank, or at an NftPool for non-fungibles paying the pool's fee buy-in from this account
+        fn bank_deposit(&mut self, bank: Address, bucket: Bucket) -> Bucket {
+            if !self.is_non_fungible(bucket.resource_address()) {
+                return Component::from(bank).call::<Bucket>("deposit", vec![scrypto_encode(&bucket)]).into();
+            }
+
+            let pool = Component::from(bank);
+            let fee_resource: Address = pool.call::<Address>("get_fee_resource", vec![]);
+            let buy_in_amount: Decimal = pool.call::<Decimal>("get_buy_in", vec![scrypto_encode(&bucket.resource_address()), scrypto_encode(&bucket.amount())]);
+            let buy_in = if buy_in_amount > dec!(0) {
+                self.record_withdrawal(fee_resource, buy_in_amount);
+                let unwound = self.make_available(fee_resource, buy_in_amount);
+                let buy_in = self.local_vault(fee_resource).take(buy_in_amount);
+                self.resume_banking(fee_resource, unwound);
+                buy_in
+            } else {
+                Bucket::new(fee_resource)
+            };
+
+            let mut returned: Vec<Bucket> = pool.call::<Vec<Bucket>>("deposit", vec![scrypto_encode(&bucket), scrypto_encode(&buy_in)]);
+            let change = returned.pop().unwrap();
+            self.store(change);
+            returned.pop().unwrap()
+        }
+
+        // redeems lender tokens, or pool receipts for the nfts with the same keys
+        fn bank_withdraw(&mut self, resource_address: Address, bank: Address, lenders: Bucket) -> Bucket {
+            if self.is_non_fungible(resource_address) {
+                return self.pool_withdraw(bank, lenders);
+            }
+            Component::from(bank).call::<Bucket>("withdraw", vec![scrypto_encode(&lenders)]).into()
+        }
+
+        // redeems pool receipts for their nfts, the depositor fees they earned go into this account
+        fn pool_withdraw(&mut self, pool: Address, receipts: Bucket) -> Bucket {
+            let mut returned: Vec<Bucket> = Component::from(pool).call::<Vec<Bucket>>("withdraw", vec![scrypto_encode(&receipts)]);
+            let fees = returned.pop().unwrap();
+            self.store(fees);
+            returned.pop().unwrap()
+        }
+
+        fn is_non_fungible(&self, resource_address: Address) -> bool {
+            match ResourceDef::from(resource_address).resource_type() {
+                ResourceType::NonFungible => true,
+                _ => false
+            }
+        }
+
+        fn slot(&self, resource_address: Address) -> TokenSlot {
+            match self.slots.get(&resource_address) {
+                Some(slot) => slot,
+                None => panic!("Insufficient balance")
+            }
+        }
+
+        fn local_vault(&self, resource_address: Address) -> Vault {
+            self.vaults.get(&resource_address).unwrap()
+        }
+
+        // vault holding the lender tokens of a banked token
+        fn lender_vault(&self, slot: &TokenSlot) -> Vault {
+            self.vaults.get(&slot.bank_token_address.unwrap()).unwrap()
+        }
+
+        // up to `limit` entries, newest first, skipping the `offset` newest
+        pub fn get_history(&self, offset: u64, limit: u64) -> Vec<HistoryEntry> {
+            let kept = if self.history_count < MAX_HISTORY {self.history_count} else {MAX_HISTORY};
+            let end = if offset.saturating_add(limit) < kept {offset + limit} else {kept};
+            (offset..end)
+                .map(|i| self.history.get(&((self.history_count - 1 - i) % MAX_HISTORY)).unwrap())
+                .collect()
+        }
+
+        fn record_history(&mut self, kind: HistoryKind, resource_address: Address, amount: Decimal, counterparty: Option<Address>) {
+            // banking toggles are worth recording even for an empty balance, zero amounts of anything else aren't
+            match kind {
+                HistoryKind::Bank | HistoryKind::Unbank => {}
+                _ if amount == dec!(0) => return,
+                _ => {}
+            }
+            let id = self.history_count;
+            self.history.insert(id % MAX_HISTORY, HistoryEntry {
+                id,
+                kind,
+                resource_address,
+                amount,
+                epoch: Context::current_epoch(),
+                counterparty,
+            });
+            self.history_count += 1;
+        }
+
+        // anyone can deposit or flash borrow, so dust below the token's history minimum could push real entries out
+        fn record_permissionless(&mut self, kind: HistoryKind, resource_address: Address, amount: Decimal, counterparty: Option<Address>) {
+            if amount >= self.slot(resource_address).history_minimum {
+                self.record_history(kind, resource_address, amount, counterparty);
+            }
+        }
+
+        pub fn set_history_minimum(
+            &mut self,
+            resource_address: Address,
+            minimum: Decimal,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            assert!(minimum >= dec!(0), "The history minimum cannot be negative");
+            let mut slot = self.slot(resource_address);
+            slot.history_minimum = minimum;
+            self.slots.insert(resource_address, slot);
+        }
+
+        pub fn get_token_slot(&self, resource_address: Address) -> TokenSlot {
+            self.slot(resource_address)
+        }
+
+        pub fn get_balance(&self, resource_address: Address) -> TokenBalance {
+            let slot = self.slot(resource_address);
+            let lender_tokens = match slot.bank_token_address {
+                Some(_) => self.lender_vault(&slot).amount(),
+                None => dec!(0)
+            };
+            let underlying = if lender_tokens > dec!(0) {
+                let rate: Decimal = Component::from(slot.bank.unwrap()).call::<Decimal>("get_exchange_rate", vec![scrypto_encode(&resource_address)]);
+                lender_tokens * rate
+            } else {
+                dec!(0)
+            };
+
+            TokenBalance {
+                resource_address,
+                local: self.local_vault(resource_address).amount(),
+                lender_tokens,
+                underlying,
+                is_using_bank: slot.is_using_bank,
+                is_borrowable: slot.is_borrowable,
+                loan_interest: slot.loan_interest,
+            }
+        }
+
+        // balances of the tokens at indexes offset..offset + limit, lender tokens are reported under the token they were issued for
+        pub fn get_all_balances(&self, offset: u64, limit: u64) -> Vec<TokenBalance> {
+            let end = if offset.saturating_add(limit) < self.token_count {offset + limit} else {self.token_count};
+            (offset..end)
+                .map(|index| self.tokens.get(&index).unwrap())
+                .filter(|address| self.lender_lookup.get(address).is_none())
+                .map(|address| self.get_balance(address))
+                .collect()
+        }
+
+        // number of token indexes get_all_balances and claim_inheritance page through
+        pub fn get_token_count(&self) -> u64 {
+            self.token_count
+        }
+
         pub fn set_borrowable(
             &mut self,
             resource_address: Address,
             account_auth: BucketRef,
         ) -> () {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
+            self.check_owner(account_auth);
 
-            let (vault, bank_token_address, is_using_bank, local_storage_is_borrowable, loan_interest) = self.vaults.get(&resource_address).unwrap();
-            assert!(!local_storage_is_borrowable, "That loken is already borrowable");
-            self.vaults.insert(resource_address, (vault, bank_token_address, is_using_bank, true, loan_interest) );
+            let mut slot = self.slot(resource_address);
+            assert!(!slot.is_borrowable, "That token is already borrowable");
+            slot.is_borrowable = true;
+            self.slots.insert(resource_address, slot);
         }
 
         pub fn set_unborrowable(
@@ -241,11 +1777,12 @@ blueprint! {
             resource_address: Address,
             account_auth: BucketRef,
         ) -> () {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
+            self.check_owner(account_auth);
 
-            let (vault, bank_token_address, is_using_bank, local_storage_is_borrowable, loan_interest) = self.vaults.get(&resource_address).unwrap();
-            assert!(local_storage_is_borrowable, "That loken is already borrowable");
-            self.vaults.insert(resource_address, (vault, bank_token_address, is_using_bank, false, loan_interest) );
+            let mut slot = self.slot(resource_address);
+            assert!(slot.is_borrowable, "That token is already unborrowable");
+            slot.is_borrowable = false;
+            self.slots.insert(resource_address, slot);
         }
 
         pub fn set_interest_rate(
@@ -254,50 +1791,171 @@ blueprint! {
             interest_rate: Decimal,
             account_auth: BucketRef,
         ) -> () {
-            account_auth.check_non_fungible_key(ECDSA_TOKEN, |key| key == &self.non_fungible_key());
+            self.check_owner(account_auth);
 
-            let (vault, bank_token_address, is_using_bank, local_storage_is_borrowable, _loan_interest) = self.vaults.get(&resource_address).unwrap();
-            self.vaults.insert(resource_address, (vault, bank_token_address, is_using_bank, local_storage_is_borrowable, interest_rate) );
+            let mut slot = self.slot(resource_address);
+            slot.loan_interest = interest_rate;
+            self.slots.insert(resource_address, slot);
         }
 
         // modified flash loan code from tweeted repo
         pub fn request_loan(&mut self, amount: Decimal, resource_address: Address, component_address: Address) -> Bucket {
-            let vault = self.vaults.get(&resource_address);
-            match vault {
-                Some((mut vault, _bank_token_address, _is_using_bank, local_storage_is_borrowable, loan_interest)) => {
-                    if local_storage_is_borrowable{
-                        assert!(amount <= vault.amount(), "Not enough funds to loan");
+            self.lend(amount, resource_address, component_address, false)
+        }
 
-                        // Call the execute method at the specified component's address with the requested funds
-                        let args = vec![
-                            scrypto_encode(&vault.take(amount))
-                        ];
+        // lends `amount`, or whatever redeeming the lender tokens yielded when `up_to` is set and that falls short
+        fn lend(&mut self, amount: Decimal, resource_address: Address, component_address: Address, up_to: bool) -> Bucket {
+            let slot = self.slot(resource_address);
+            if !slot.is_borrowable {
+                panic!("vault is not allowed to be borrowed from")
+            }
+            let mut vault = self.local_vault(resource_address);
 
-                        let mut returned_bucket: Bucket = Component::from(component_address).call::<Bucket>("execute", args).into();
+            // a banked balance is lent by redeeming just the lender tokens the loan needs for its length
+            let mut round_trip_cost = dec!(0);
+            let redeemed = slot.lend_banked && slot.is_using_bank && amount > vault.amount();
+            if redeemed {
+                let local_before = vault.amount();
+                let value_before = self.banked_value(resource_address);
+                self.redeem_underlying(resource_address, amount - local_before);
+                // rounding in the exchange rate can leave the vault a hair short
+                if vault.amount() < amount {
+                    self.unwind_bank(resource_address);
+                }
+                let received = vault.amount() - local_before;
+                let redeemed_value = value_before - self.banked_value(resource_address);
+                if redeemed_value > received {
+                    round_trip_cost = redeemed_value - received;
+                }
+            }
+            let amount = if up_to && amount > vault.amount() {vault.amount()} else {amount};
+            assert!(amount <= vault.amount(), "Not enough funds to loan");
+            self.record_permissionless(HistoryKind::FlashLoan, resource_address, amount, Some(component_address));
 
-                        // Make sure they repaid in loan in full
-                        let amount_to_take = amount * ((loan_interest / 100) + 1);
-                        assert!(returned_bucket.amount() >= amount_to_take, "You have to return more than {}", amount_to_take);
+            // Call the execute method at the specified component's address with the requested funds
+            let args = vec![
+                scrypto_encode(&vault.take(amount))
+            ];
 
-                        vault.put(returned_bucket.take(amount_to_take));
+            let mut returned_bucket: Bucket = Component::from(component_address).call::<Bucket>("execute", args).into();
 
-                        // Return the change back to the component
-                        return returned_bucket;
-                    }else{
-                        panic!("vault is not allowed to be borrowed from")
-                    }
+            // Make sure they repaid in loan in full
+            let interest_amount = amount * slot.loan_interest / 100;
+            let amount_to_take = amount + round_trip_cost + interest_amount;
+            assert!(returned_bucket.amount() >= amount_to_take, "You have to return more than {}", amount_to_take);
+
+            let mut repayment = returned_bucket.take(amount_to_take);
+            let interest = repayment.take(interest_amount);
+            vault.put(repayment);
+            self.collect_earnings(resource_address, interest);
+            if redeemed {
+                self.bank_excess(resource_address);
+            }
+
+            // Return the change back to the component
+            returned_bucket
+        }
+
+        // lets flash loans of this token dip into its banked balance
+        pub fn set_lend_banked(
+            &mut self,
+            resource_address: Address,
+            lend_banked: bool,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            assert!(!self.is_non_fungible(resource_address), "Non-fungible tokens cannot be flash loaned");
+            let mut slot = self.slot(resource_address);
+            slot.lend_banked = lend_banked;
+            self.slots.insert(resource_address, slot);
+        }
+
+        // records flash loan interest and sends it where the owner wants it
+        fn collect_earnings(&mut self, resource_address: Address, interest: Bucket) {
+            let mut slot = self.slot(resource_address);
+            slot.loan_earnings = slot.loan_earnings + interest.amount();
+            let route_to_bank = slot.earnings_route == EarningsRoute::Bank && slot.is_using_bank;
+            let keep_separate = slot.earnings_route == EarningsRoute::Separate;
+            self.slots.insert(resource_address, slot);
+
+            if route_to_bank {
+                let slot = self.slot(resource_address);
+                let lended_tokens = self.bank_deposit(slot.bank.unwrap(), interest);
+                self.lender_vault(&slot).put(lended_tokens);
+            }else if keep_separate {
+                match self.earnings.get(&resource_address) {
+                    Some(mut v) => v.put(interest),
+                    None => self.earnings.insert(resource_address, Vault::with_bucket(interest))
+                }
+            }else{
+                self.local_vault(resource_address).put(interest);
+            }
+        }
+
+        pub fn set_earnings_route(
+            &mut self,
+            resource_address: Address,
+            earnings_route: EarningsRoute,
+            account_auth: BucketRef,
+        ) -> () {
+            self.check_owner(account_auth);
+
+            let mut slot = self.slot(resource_address);
+            slot.earnings_route = earnings_route;
+            self.slots.insert(resource_address, slot);
+        }
+
+        // returns (cumulative flash loan interest earned, interest waiting in the earnings vault)
+        pub fn get_loan_earnings(&self, resource_address: Address) -> (Decimal, Decimal) {
+            let slot = self.slot(resource_address);
+            let held = match self.earnings.get(&resource_address) {
+                Some(v) => v.amount(),
+                None => dec!(0)
+            };
+            (slot.loan_earnings, held)
+        }
+
+        pub fn withdraw_earnings(
+            &mut self,
+            resource_address: Address,
+            account_auth: BucketRef,
+        ) -> Bucket {
+            match self.earnings.get(&resource_address) {
+                Some(mut v) => {
+                    // earnings sit outside the balance savings goals lock
+                    self.check_signers(resource_address, v.amount(), account_auth);
+                    self.count_against_limit(resource_address, v.amount());
+                    self.record_history(HistoryKind::Withdrawal, resource_address, v.amount(), None);
+                    v.take_all()
                 }
                 None => {
-                    panic!("Insufficient balance")
+                    self.check_owner(account_auth);
+                    Bucket::new(resource_address)
                 }
             }
         }
 
-        // lends out all the cash in the vault
+        // annualized percentage the bank has paid lenders of this token over the last `epochs` epochs
+        pub fn get_yield(&self, resource_address: Address, epochs: u64) -> Decimal {
+            let bank = match self.slots.get(&resource_address) {
+                Some(TokenSlot { bank: Some(bank), .. }) => bank,
+                _ => self.bank
+            };
+            Component::from(bank).call::<Decimal>("get_apy", vec![scrypto_encode(&resource_address), scrypto_encode(&epochs)])
+        }
+
+        // lends out all the cash in the vault, and the banked balance too when lend_banked is set
         pub fn request_max_loan(&mut self, currency: Address, component_address: Address) -> Bucket {
             match self.vaults.get(&currency){
-                Some((vault, _bank_token_address, _is_using_bank, _local_storage_is_borrowable, _loan_interest)) =>{
-                    self.request_loan(vault.amount(), currency, component_address)
+                Some(vault) =>{
+                    let slot = self.slot(currency);
+                    let amount = if slot.lend_banked && slot.is_using_bank {
+                        vault.amount() + self.banked_value(currency)
+                    } else {
+                        vault.amount()
+                    };
+                    self.lend(amount, currency, component_address, true)
                 }
                 None =>{
                     panic!("No liquidity for this token is available")
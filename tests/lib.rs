@@ -1,7 +1,28 @@
 use radix_engine::ledger::*;
+use radix_engine::model::*;
 use radix_engine::transaction::*;
 use scrypto::prelude::*;
 
+// argument types of the blueprints, encoded the same way
+#[derive(TypeId, Encode, Decode)]
+enum BufferTarget {
+    Percent(Decimal),
+    Fixed(Decimal),
+}
+
+#[derive(TypeId, Encode, Decode)]
+enum EarningsRoute {
+    Compound,
+    Separate,
+    Bank,
+}
+
+#[derive(TypeId, Encode, Decode)]
+enum RoutingMode {
+    Weighted,
+    BestApy,
+}
+
 #[test]
 fn test_bank() {
     // Set up environment.
@@ -55,3 +76,1442 @@ fn test_bank() {
     println!("{:?}\n", receipt2);
     assert!(receipt2.result.is_ok());
 }
+
+#[test]
+fn test_flash_loan_cannot_manipulate_exchange_rate() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "Bank", "new", vec!["0.09".to_owned(), "5".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let bank = receipt1.component(0).unwrap();
+
+    // Provide the liquidity that will be borrowed.
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(bank, "deposit", vec![format!("1000,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    assert!(receipt2.result.is_ok());
+
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_function(
+            package,
+            "FlashLoanAttacker",
+            "new",
+            vec![format!("200,{}", RADIX_TOKEN), bank.to_string()],
+            Some(account),
+        )
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt3 = executor.run(transaction3).unwrap();
+    assert!(receipt3.result.is_ok());
+    let attacker = receipt3.component(0).unwrap();
+
+    // The attacker deposits into the bank while 90% of the vault is lent to it.
+    let transaction4 = TransactionBuilder::new(&executor)
+        .call_method(
+            bank,
+            "request_loan",
+            vec!["900".to_owned(), RADIX_TOKEN.to_string(), attacker.to_string()],
+            None,
+        )
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt4 = executor.run(transaction4).unwrap();
+    println!("{:?}\n", receipt4);
+    assert!(receipt4.result.is_ok());
+
+    // Redeeming after repayment must not return more than the attacker put in.
+    let transaction5 = TransactionBuilder::new(&executor)
+        .call_method(attacker, "cash_out", vec![], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt5 = executor.run(transaction5).unwrap();
+    println!("{:?}\n", receipt5);
+    assert!(receipt5.result.is_ok());
+}
+
+#[test]
+fn test_borrow_policies_respect_liquidity_buffer() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "Bank", "new", vec!["0.09".to_owned(), "5".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let bank = receipt1.component(0).unwrap();
+    let admin_badge = receipt1.resource_def(1).unwrap();
+
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(bank, "deposit", vec![format!("1000,{}", RADIX_TOKEN)], Some(account))
+        .call_function(package, "BankTester", "new", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method(bank, "set_liquidity_buffer", vec!["10".to_owned(), format!("1,{}", admin_badge)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    assert!(receipt2.result.is_ok());
+    let borrower = receipt2.component(0).unwrap();
+
+    // 10% of the pool is never lent out.
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(bank, "request_loan", vec!["950".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt3 = executor.run(transaction3).unwrap();
+    assert!(receipt3.result.is_err());
+
+    // After the first loan part of the vault backs the bank's cut, so the second loan is a little smaller.
+    let transaction4 = TransactionBuilder::new(&executor)
+        .call_method(bank, "request_loan", vec!["900".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method(bank, "request_loan_upto", vec!["950".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method(bank, "request_max_loan", vec![RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt4 = executor.run(transaction4).unwrap();
+    println!("{:?}\n", receipt4);
+    assert!(receipt4.result.is_ok());
+
+    // Nothing to lend returns an empty bucket instead of panicking.
+    let transaction5 = TransactionBuilder::new(&executor)
+        .call_method(bank, "request_max_loan", vec![admin_badge.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt5 = executor.run(transaction5).unwrap();
+    println!("{:?}\n", receipt5);
+    assert!(receipt5.result.is_ok());
+
+    // The bank's cut can still be withdrawn in full after lending as much as possible.
+    let transaction6 = TransactionBuilder::new(&executor)
+        .call_method(bank, "request_max_loan", vec![RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method(bank, "withdraw_bank_cut", vec![RADIX_TOKEN.to_string(), format!("1,{}", admin_badge)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt6 = executor.run(transaction6).unwrap();
+    println!("{:?}\n", receipt6);
+    assert!(receipt6.result.is_ok());
+}
+
+#[test]
+fn test_rate_snapshots_and_apy() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "Bank", "new", vec!["0.09".to_owned(), "5".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let bank = receipt1.component(0).unwrap();
+    let admin_badge = receipt1.resource_def(1).unwrap();
+
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(bank, "deposit", vec![format!("1000,{}", RADIX_TOKEN)], Some(account))
+        .call_function(package, "BankTester", "new", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    assert!(receipt2.result.is_ok());
+    let borrower = receipt2.component(0).unwrap();
+
+    // Loan fees raise the exchange rate, the next epoch's first call snapshots it.
+    executor.ledger_mut().set_epoch(10);
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(bank, "request_loan", vec!["500".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction3).unwrap().result.is_ok());
+
+    executor.ledger_mut().set_epoch(20);
+    let transaction4 = TransactionBuilder::new(&executor)
+        .call_method(bank, "get_apy", vec![RADIX_TOKEN.to_string(), "15".to_owned()], None)
+        .call_method(bank, "get_apy_since_inception", vec![RADIX_TOKEN.to_string()], None)
+        .call_method(bank, "get_rate_history", vec![RADIX_TOKEN.to_string(), "0".to_owned(), "10".to_owned()], None)
+        .call_method(bank, "get_apy", vec![admin_badge.to_string(), "15".to_owned()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt4 = executor.run(transaction4).unwrap();
+    println!("{:?}\n", receipt4);
+    assert!(receipt4.result.is_ok());
+
+    // Only the admin can change how epochs are annualized.
+    let transaction5 = TransactionBuilder::new(&executor)
+        .call_method(bank, "set_epochs_per_year", vec!["365".to_owned(), format!("1,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction5).unwrap().result.is_err());
+    let transaction6 = TransactionBuilder::new(&executor)
+        .call_method(bank, "set_epochs_per_year", vec!["365".to_owned(), format!("1,{}", admin_badge)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction6).unwrap().result.is_ok());
+}
+
+
+#[test]
+fn test_migrated_pool_deposits_withdraws_and_migrates() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "Bank", "new", vec!["0.09".to_owned(), "5".to_owned()], None)
+        .call_function(package, "Bank", "new", vec!["0.09".to_owned(), "5".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let old_bank = receipt1.component(0).unwrap();
+    let new_bank = receipt1.component(1).unwrap();
+    let old_admin_badge = receipt1.resource_def(1).unwrap();
+
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(old_bank, "deposit", vec![format!("1000,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    assert!(receipt2.result.is_ok());
+    let old_lender_token = receipt2.resource_def(0).unwrap();
+
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(
+            old_bank,
+            "migrate_pool",
+            vec![RADIX_TOKEN.to_string(), new_bank.to_string(), format!("1,{}", old_admin_badge)],
+            Some(account),
+        )
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt3 = executor.run(transaction3).unwrap();
+    println!("{:?}\n", receipt3);
+    assert!(receipt3.result.is_ok());
+
+    // The old bank keeps working through the successor.
+    let transaction4 = TransactionBuilder::new(&executor)
+        .call_method(old_bank, "deposit", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method(old_bank, "withdraw", vec![format!("100,{}", old_lender_token)], Some(account))
+        .call_method(old_bank, "migrate", vec![format!("100,{}", old_lender_token)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt4 = executor.run(transaction4).unwrap();
+    println!("{:?}\n", receipt4);
+    assert!(receipt4.result.is_ok());
+}
+
+#[test]
+fn test_fee_policy_replaces_loan_interest() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "Bank", "new", vec!["0.09".to_owned(), "5".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let bank = receipt1.component(0).unwrap();
+    let admin_badge = receipt1.resource_def(1).unwrap();
+
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(bank, "deposit", vec![format!("1000,{}", RADIX_TOKEN)], Some(account))
+        .call_function(package, "BankTester", "new", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    assert!(receipt2.result.is_ok());
+    let borrower = receipt2.component(0).unwrap();
+
+    // Only the admin can change the fee policy.
+    let not_a_policy = vec![scrypto_encode(&Some(borrower))];
+    let receipt3 = call_with_badge(&mut executor, account, key, bank, "set_fee_policy", not_a_policy.clone(), RADIX_TOKEN);
+    assert!(receipt3.result.is_err());
+    let receipt4 = call_with_badge(&mut executor, account, key, bank, "set_fee_policy", not_a_policy, admin_badge);
+    assert!(receipt4.result.is_ok());
+
+    // Loans ask the policy for the fee, a component without loan_fee can't quote one.
+    let loan = TransactionBuilder::new(&executor)
+        .call_method(bank, "request_loan", vec!["500".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(loan).unwrap().result.is_err());
+
+    // Without a policy the loan interest applies again.
+    let receipt6 = call_with_badge(&mut executor, account, key, bank, "set_fee_policy", vec![scrypto_encode(&None::<Address>)], admin_badge);
+    assert!(receipt6.result.is_ok());
+    let loan = TransactionBuilder::new(&executor)
+        .call_method(bank, "request_loan", vec!["500".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt7 = executor.run(loan).unwrap();
+    println!("{:?}\n", receipt7);
+    assert!(receipt7.result.is_ok());
+}
+
+#[test]
+fn test_partial_banking_keeps_a_liquid_buffer() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let too_large = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&BufferTarget::Percent(dec!(150)))];
+    assert!(call_signed(&mut executor, account, savings, "set_buffer_target", too_large, vec![key]).result.is_err());
+    let negative = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&BufferTarget::Fixed(dec!(-1)))];
+    assert!(call_signed(&mut executor, account, savings, "set_buffer_target", negative, vec![key]).result.is_err());
+
+    // 10 stays in the account, 90 goes to the bank.
+    let buffer = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&BufferTarget::Percent(dec!(10)))];
+    assert!(call_signed(&mut executor, account, savings, "set_buffer_target", buffer, vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // Small withdrawals come out of the buffer, larger ones redeem just what they need.
+    let withdraw_5 = vec![scrypto_encode(&dec!(5)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_5, vec![key]).result.is_ok());
+    let withdraw_50 = vec![scrypto_encode(&dec!(50)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_50, vec![key]).result.is_ok());
+
+    let fixed = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&BufferTarget::Fixed(dec!(20)))];
+    assert!(call_signed(&mut executor, account, savings, "set_buffer_target", fixed, vec![key]).result.is_ok());
+    let withdraw_45 = vec![scrypto_encode(&dec!(45)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_45.clone(), vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_45, vec![key]).result.is_err());
+}
+
+#[test]
+fn test_rebalancing_banked_tokens() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    // Only banked tokens can be rebalanced.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "rebalance", vec![RADIX_TOKEN.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction1).unwrap().result.is_err());
+
+    let too_large = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!(150))];
+    assert!(call_signed(&mut executor, account, savings, "set_rebalance_threshold", too_large, vec![key]).result.is_err());
+    let threshold = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!(5))];
+    assert!(call_signed(&mut executor, account, savings, "set_rebalance_threshold", threshold, vec![key]).result.is_ok());
+    let buffer = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&BufferTarget::Percent(dec!(10)))];
+    assert!(call_signed(&mut executor, account, savings, "set_buffer_target", buffer, vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // A small deposit stays in the buffer, a large one is banked, and anyone can rebalance.
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(savings, "deposit", vec![format!("3,{}", RADIX_TOKEN)], Some(account))
+        .call_method(savings, "deposit", vec![format!("50,{}", RADIX_TOKEN)], Some(account))
+        .call_method(savings, "rebalance", vec![RADIX_TOKEN.to_string()], None)
+        .call_method(savings, "get_balance", vec![RADIX_TOKEN.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    println!("{:?}\n", receipt2);
+    assert!(receipt2.result.is_ok());
+
+    let withdraw_all = vec![scrypto_encode(&dec!(153)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_all, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_token_slot_settings() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    // Tokens the account never held have no slot.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "get_token_slot", vec![bank.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction1).unwrap().result.is_err());
+
+    assert!(call_signed(&mut executor, account, savings, "set_borrowable", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "set_borrowable", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_err());
+    let interest = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!("0.5"))];
+    assert!(call_signed(&mut executor, account, savings, "set_interest_rate", interest, vec![key]).result.is_ok());
+
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(savings, "get_token_slot", vec![RADIX_TOKEN.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    println!("{:?}\n", receipt2);
+    assert!(receipt2.result.is_ok());
+
+    assert!(call_signed(&mut executor, account, savings, "set_unborrowable", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "set_unborrowable", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_err());
+}
+
+#[test]
+fn test_tokens_banked_at_their_own_bank() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let default_bank = new_bank(&mut executor, account, key, package);
+    let other_bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, default_bank);
+
+    // Choosing a bank before banking, then banking there.
+    let choose_other = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&other_bank)];
+    assert!(call_signed(&mut executor, account, savings, "set_bank_for_token", choose_other, vec![key]).result.is_ok());
+    executor.ledger_mut().set_epoch(24);
+    assert!(finish_bank_change(&mut executor, key, savings, RADIX_TOKEN).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // Switching banks moves the banked funds over.
+    let choose_default = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&default_bank)];
+    assert!(call_signed(&mut executor, account, savings, "set_bank_for_token", choose_default, vec![key]).result.is_ok());
+    executor.ledger_mut().set_epoch(48);
+    assert!(finish_bank_change(&mut executor, key, savings, RADIX_TOKEN).result.is_ok());
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(other_bank, "get_balance", vec![RADIX_TOKEN.to_string()], None)
+        .call_method(default_bank, "get_balance", vec![RADIX_TOKEN.to_string()], None)
+        .call_method(savings, "get_yield", vec![RADIX_TOKEN.to_string(), "10".to_owned()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    println!("{:?}\n", receipt1);
+    assert!(receipt1.result.is_ok());
+
+    let withdraw_all = vec![scrypto_encode(&dec!(100)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_all, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_router_skips_banks_with_zero_weight() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let open_bank = new_bank(&mut executor, account, key, package);
+    let closed_bank = new_bank(&mut executor, account, key, package);
+
+    let mut builder = TransactionBuilder::new(&executor);
+    builder.add_instruction(Instruction::CallFunction {
+        package_address: package,
+        blueprint_name: "BankRouter".to_owned(),
+        function: "new".to_owned(),
+        args: vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&RoutingMode::Weighted), scrypto_encode(&10u64)],
+    });
+    let transaction1 = builder
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let router = receipt1.component(0).unwrap();
+    let admin_badge = receipt1.resource_def(1).unwrap();
+    let router_token = receipt1.resource_def(2).unwrap();
+
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(router, "add_bank", vec![open_bank.to_string(), "1".to_owned(), format!("1,{}", admin_badge)], Some(account))
+        .call_method(router, "add_bank", vec![closed_bank.to_string(), "0".to_owned(), format!("1,{}", admin_badge)], Some(account))
+        .call_method(router, "deposit", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    println!("{:?}\n", receipt2);
+    assert!(receipt2.result.is_ok());
+
+    // Best APY mode doesn't pick a closed bank either.
+    let best_apy = vec![scrypto_encode(&RoutingMode::BestApy)];
+    assert!(call_with_badge(&mut executor, account, key, router, "set_mode", best_apy, admin_badge).result.is_ok());
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(router, "deposit", vec![format!("50,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction3).unwrap().result.is_ok());
+    let transaction4 = TransactionBuilder::new(&executor)
+        .call_method(closed_bank, "get_balance", vec![RADIX_TOKEN.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction4).unwrap().result.is_err());
+
+    // Swapping the weights sends new deposits to the other bank, withdrawals still come from both.
+    let transaction5 = TransactionBuilder::new(&executor)
+        .call_method(router, "set_weight", vec![closed_bank.to_string(), "1".to_owned(), format!("1,{}", admin_badge)], Some(account))
+        .call_method(router, "set_weight", vec![open_bank.to_string(), "0".to_owned(), format!("1,{}", admin_badge)], Some(account))
+        .call_method(router, "deposit", vec![format!("10,{}", RADIX_TOKEN)], Some(account))
+        .call_method(closed_bank, "get_balance", vec![RADIX_TOKEN.to_string()], None)
+        .call_method(router, "get_apy", vec![RADIX_TOKEN.to_string(), "10".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt5 = executor.run(transaction5).unwrap();
+    println!("{:?}\n", receipt5);
+    assert!(receipt5.result.is_ok());
+
+    let transaction6 = TransactionBuilder::new(&executor)
+        .call_method(router, "withdraw", vec![format!("160,{}", router_token)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt6 = executor.run(transaction6).unwrap();
+    println!("{:?}\n", receipt6);
+    assert!(receipt6.result.is_ok());
+
+    // A bank can only be registered once.
+    let transaction7 = TransactionBuilder::new(&executor)
+        .call_method(router, "add_bank", vec![open_bank.to_string(), "1".to_owned(), format!("1,{}", admin_badge)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction7).unwrap().result.is_err());
+}
+
+#[test]
+fn test_balances_include_banked_tokens() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // The lender tokens get their own index but are reported under XRD.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "get_balance", vec![RADIX_TOKEN.to_string()], None)
+        .call_method(savings, "get_token_count", vec![], None)
+        .call_method(savings, "get_all_balances", vec!["0".to_owned(), "10".to_owned()], None)
+        .call_method(savings, "get_all_balances", vec!["5".to_owned(), "10".to_owned()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    println!("{:?}\n", receipt1);
+    assert!(receipt1.result.is_ok());
+}
+
+#[test]
+fn test_withdrawals_unwind_banked_tokens() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    // Without a buffer everything is banked, so every withdrawal redeems lender tokens.
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+    let withdraw_30 = vec![scrypto_encode(&dec!(30)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_30, vec![key]).result.is_ok());
+    let withdraw_70 = vec![scrypto_encode(&dec!(70)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_70.clone(), vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_70, vec![key]).result.is_err());
+
+    // The token stays banked, new deposits go back to the bank.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "deposit", vec![format!("10,{}", RADIX_TOKEN)], Some(account))
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction1).unwrap().result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "unbank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "unbank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_err());
+}
+
+#[test]
+fn test_pooled_nfts_come_back_to_their_depositor() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let first = new_savings_account(&mut executor, account, key, package, bank);
+    let second = new_savings_account(&mut executor, account, key, package, bank);
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "NftPool", "new", vec![RADIX_TOKEN.to_string(), "1".to_owned()], None)
+        .call_function(package, "NftTester", "new", vec!["4".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let pool = receipt1.component(0).unwrap();
+    let tester = receipt1.component(1).unwrap();
+    let admin_badge = receipt1.resource_def(1).unwrap();
+    let collection = receipt1.resource_def(2).unwrap();
+
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(pool, "list_collection", vec![collection.to_string(), format!("1,{}", admin_badge)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction2).unwrap().result.is_ok());
+
+    // Each account pools two nfts of the same collection.
+    give_nfts(&mut executor, key, tester, &[0, 1], first);
+    give_nfts(&mut executor, key, tester, &[2, 3], second);
+    for savings in [first, second] {
+        let choose_pool = vec![scrypto_encode(&collection), scrypto_encode(&pool)];
+        assert!(call_signed(&mut executor, account, savings, "set_bank_for_token", choose_pool, vec![key]).result.is_ok());
+    }
+    executor.ledger_mut().set_epoch(24);
+    for savings in [first, second] {
+        assert!(finish_bank_change(&mut executor, key, savings, collection).result.is_ok());
+        assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&collection)], vec![key]).result.is_ok());
+    }
+
+    // Banked nfts are withdrawn by key, but only the account's own.
+    let own = vec![scrypto_encode(&nft_keys(&[0])), scrypto_encode(&collection)];
+    assert!(call_signed(&mut executor, account, first, "withdraw_non_fungibles", own, vec![key]).result.is_ok());
+    let others = vec![scrypto_encode(&nft_keys(&[2])), scrypto_encode(&collection)];
+    assert!(call_signed(&mut executor, account, first, "withdraw_non_fungibles", others, vec![key]).result.is_err());
+
+    // Unbanking returns exactly the nfts that were pooled.
+    assert!(call_signed(&mut executor, account, second, "unbank_token", vec![scrypto_encode(&collection)], vec![key]).result.is_ok());
+    let both = vec![scrypto_encode(&nft_keys(&[2, 3])), scrypto_encode(&collection)];
+    assert!(call_signed(&mut executor, account, second, "withdraw_non_fungibles", both, vec![key]).result.is_ok());
+
+    // Pooling a redeemed nft again hands out its old receipt.
+    assert!(call_signed(&mut executor, account, first, "unbank_token", vec![scrypto_encode(&collection)], vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, first, "bank_token", vec![scrypto_encode(&collection)], vec![key]).result.is_ok());
+    let last = vec![scrypto_encode(&nft_keys(&[1])), scrypto_encode(&collection)];
+    assert!(call_signed(&mut executor, account, first, "withdraw_non_fungibles", last, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_flash_loan_earnings_go_where_the_owner_wants() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "BankTester", "new", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let borrower = receipt1.component(0).unwrap();
+    let loan = |executor: &mut TransactionExecutor<InMemorySubstateStore>| {
+        let transaction = TransactionBuilder::new(&*executor)
+            .call_method(savings, "request_loan", vec!["50".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+            .call_method_with_all_resources(account, "deposit_batch")
+            .build(vec![key])
+            .unwrap();
+        executor.run(transaction).unwrap()
+    };
+
+    // Tokens aren't lent until the owner allows it.
+    assert!(loan(&mut executor).result.is_err());
+    assert!(call_signed(&mut executor, account, savings, "set_borrowable", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    let separate = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&EarningsRoute::Separate)];
+    assert!(call_signed(&mut executor, account, savings, "set_earnings_route", separate, vec![key]).result.is_ok());
+    assert!(loan(&mut executor).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "withdraw_earnings", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // Earnings routed to the bank are banked with the rest when the token is banked.
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+    let to_bank = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&EarningsRoute::Bank)];
+    assert!(call_signed(&mut executor, account, savings, "set_earnings_route", to_bank, vec![key]).result.is_ok());
+    assert!(loan(&mut executor).result.is_err());
+    let buffer = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&BufferTarget::Fixed(dec!(60)))];
+    assert!(call_signed(&mut executor, account, savings, "set_buffer_target", buffer, vec![key]).result.is_ok());
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(savings, "rebalance", vec![RADIX_TOKEN.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction2).unwrap().result.is_ok());
+    assert!(loan(&mut executor).result.is_ok());
+
+    let compound = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&EarningsRoute::Compound)];
+    assert!(call_signed(&mut executor, account, savings, "set_earnings_route", compound, vec![key]).result.is_ok());
+    assert!(loan(&mut executor).result.is_ok());
+
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(savings, "get_loan_earnings", vec![RADIX_TOKEN.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt3 = executor.run(transaction3).unwrap();
+    println!("{:?}\n", receipt3);
+    assert!(receipt3.result.is_ok());
+}
+
+#[test]
+fn test_flash_loans_from_the_banked_balance() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "BankTester", "new", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let borrower = receipt1.component(0).unwrap();
+    assert!(call_signed(&mut executor, account, savings, "set_borrowable", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // Everything is banked, so only lending the banked balance can fill the loan.
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(savings, "request_loan", vec!["50".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction2).unwrap().result.is_err());
+
+    let lend_banked = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&true)];
+    assert!(call_signed(&mut executor, account, savings, "set_lend_banked", lend_banked, vec![key]).result.is_ok());
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(savings, "request_loan", vec!["50".to_owned(), RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method(savings, "request_max_loan", vec![RADIX_TOKEN.to_string(), borrower.to_string()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt3 = executor.run(transaction3).unwrap();
+    println!("{:?}\n", receipt3);
+    assert!(receipt3.result.is_ok());
+
+    // The loans were repaid with interest, so the full deposit can still be withdrawn.
+    let withdraw_all = vec![scrypto_encode(&dec!(100)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_all, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_bank_changes_need_the_quorum_and_wait_for_the_delay() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let second_key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let default_bank = new_bank(&mut executor, account, key, package);
+    let other_bank = new_bank(&mut executor, account, key, package);
+    let multisig = new_multisig(&mut executor, account, package, vec![key, second_key], 2, default_bank);
+
+    let delay = vec![scrypto_encode(&30u64)];
+    assert!(call_signed(&mut executor, account, multisig, "set_limit_increase_delay", delay, vec![key, second_key]).result.is_ok());
+
+    // One key alone can neither choose a bank nor bank a token.
+    let choose_other = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&other_bank)];
+    assert!(call_signed(&mut executor, account, multisig, "set_bank_for_token", choose_other.clone(), vec![key]).result.is_err());
+    assert!(call_signed(&mut executor, account, multisig, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_err());
+
+    // The chosen bank only takes over after the delay.
+    assert!(call_signed(&mut executor, account, multisig, "set_bank_for_token", choose_other, vec![key, second_key]).result.is_ok());
+    assert!(finish_bank_change(&mut executor, key, multisig, RADIX_TOKEN).result.is_err());
+    executor.ledger_mut().set_epoch(30);
+    assert!(finish_bank_change(&mut executor, key, multisig, RADIX_TOKEN).result.is_ok());
+    assert!(call_signed(&mut executor, account, multisig, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key, second_key]).result.is_ok());
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(other_bank, "get_balance", vec![RADIX_TOKEN.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    println!("{:?}\n", receipt1);
+    assert!(receipt1.result.is_ok());
+
+    // A pending change can be cancelled by the owner.
+    let choose_default = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&default_bank)];
+    assert!(call_signed(&mut executor, account, multisig, "set_bank_for_token", choose_default, vec![key, second_key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, multisig, "cancel_bank_change", vec![scrypto_encode(&RADIX_TOKEN)], vec![key, second_key]).result.is_ok());
+    executor.ledger_mut().set_epoch(60);
+    assert!(finish_bank_change(&mut executor, key, multisig, RADIX_TOKEN).result.is_err());
+}
+
+#[test]
+fn test_badge_owned_accounts() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let badge = new_badge(&mut executor, account, key);
+
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_function(package, "NftTester", "new", vec!["2".to_owned()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let tester = receipt1.component(0).unwrap();
+    let collection = receipt1.resource_def(0).unwrap();
+    give_nfts(&mut executor, key, tester, &[0], account);
+
+    // One account per kind of badge ownership, the last one owned by an nft the account doesn't hold.
+    let mut builder = TransactionBuilder::new(&executor);
+    for serial in [0u64, 1] {
+        builder.add_instruction(Instruction::CallFunction {
+            package_address: package,
+            blueprint_name: "SavingsAccount".to_owned(),
+            function: "with_owner_non_fungible".to_owned(),
+            args: vec![
+                scrypto_encode(&collection),
+                scrypto_encode(&NonFungibleKey::new(serial.to_be_bytes().to_vec())),
+                scrypto_encode(&bank),
+            ],
+        });
+    }
+    let transaction2 = builder
+        .call_function(package, "SavingsAccount", "with_owner_badge", vec![badge.to_string(), bank.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    assert!(receipt2.result.is_ok());
+    let nft_owned = receipt2.component(0).unwrap();
+    let other_nft_owned = receipt2.component(1).unwrap();
+    let badge_owned = receipt2.component(2).unwrap();
+
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(nft_owned, "deposit", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method(other_nft_owned, "deposit", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .call_method(badge_owned, "deposit", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction3).unwrap().result.is_ok());
+
+    // Neither the account's key nor another token is the badge.
+    let withdraw_10 = vec![scrypto_encode(&dec!(10)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, badge_owned, "withdraw", withdraw_10.clone(), vec![key]).result.is_err());
+    assert!(call_with_badge(&mut executor, account, key, badge_owned, "withdraw", withdraw_10.clone(), RADIX_TOKEN).result.is_err());
+    assert!(call_with_badge(&mut executor, account, key, badge_owned, "withdraw", withdraw_10.clone(), badge).result.is_ok());
+
+    // A non-fungible badge has to be the one key the account was created with.
+    assert!(call_with_badge(&mut executor, account, key, nft_owned, "withdraw", withdraw_10.clone(), collection).result.is_ok());
+    assert!(call_with_badge(&mut executor, account, key, other_nft_owned, "withdraw", withdraw_10, collection).result.is_err());
+}
+
+#[test]
+fn test_recovery_waits_for_delay_and_can_be_cancelled() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let guardian = executor.new_public_key();
+    let new_key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let guardians = vec![scrypto_encode(&vec![guardian.to_string()]), scrypto_encode(&1u64), scrypto_encode(&10u64)];
+    assert!(call_signed(&mut executor, account, savings, "set_guardians", guardians, vec![key]).result.is_ok());
+
+    let rotation = vec![scrypto_encode(&key.to_string()), scrypto_encode(&new_key.to_string())];
+    assert!(call_signed(&mut executor, account, savings, "start_recovery", rotation.clone(), vec![new_key]).result.is_err());
+    assert!(call_signed(&mut executor, account, savings, "start_recovery", rotation.clone(), vec![guardian]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "cancel_recovery", vec![], vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "start_recovery", rotation, vec![guardian]).result.is_ok());
+
+    // The owner has the recovery delay to notice and cancel.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "finish_recovery", vec![], None)
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction1).unwrap().result.is_err());
+
+    executor.ledger_mut().set_epoch(10);
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(savings, "finish_recovery", vec![], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt2 = executor.run(transaction2).unwrap();
+    println!("{:?}\n", receipt2);
+    assert!(receipt2.result.is_ok());
+
+    let withdraw_10 = vec![scrypto_encode(&dec!(10)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_10.clone(), vec![key]).result.is_err());
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_10, vec![new_key]).result.is_ok());
+}
+
+#[test]
+fn test_withdrawal_limit_increases_are_delayed() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let limit_10 = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&Some(dec!(10)))];
+    assert!(call_signed(&mut executor, account, savings, "set_withdrawal_limit", limit_10, vec![key]).result.is_ok());
+
+    let withdraw_8 = vec![scrypto_encode(&dec!(8)), scrypto_encode(&RADIX_TOKEN)];
+    let withdraw_5 = vec![scrypto_encode(&dec!(5)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_8, vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_5, vec![key]).result.is_err());
+
+    // Raising the limit only takes effect after the delay.
+    let limit_50 = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&Some(dec!(50)))];
+    assert!(call_signed(&mut executor, account, savings, "set_withdrawal_limit", limit_50, vec![key]).result.is_ok());
+    let withdraw_20 = vec![scrypto_encode(&dec!(20)), scrypto_encode(&RADIX_TOKEN)];
+    executor.ledger_mut().set_epoch(1);
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_20.clone(), vec![key]).result.is_err());
+    executor.ledger_mut().set_epoch(24);
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_20, vec![key]).result.is_ok());
+
+    // Shortening the delay is itself delayed, so it can't be used to raise a limit right away.
+    assert!(call_signed(&mut executor, account, savings, "set_limit_increase_delay", vec![scrypto_encode(&0u64)], vec![key]).result.is_ok());
+    let no_limit = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&None::<Decimal>)];
+    assert!(call_signed(&mut executor, account, savings, "set_withdrawal_limit", no_limit, vec![key]).result.is_ok());
+    executor.ledger_mut().set_epoch(25);
+    let withdraw_60 = vec![scrypto_encode(&dec!(60)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_60.clone(), vec![key]).result.is_err());
+    executor.ledger_mut().set_epoch(48);
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_60, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_spenders_pull_within_their_allowance() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+    let spender = new_badge(&mut executor, account, key);
+
+    let allowance = vec![scrypto_encode(&spender), scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!(10)), scrypto_encode(&true)];
+    assert!(call_signed(&mut executor, account, savings, "approve", allowance, vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // Pulls unwind from the bank and stop at the allowance.
+    let pull_6 = vec![scrypto_encode(&dec!(6)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_with_badge(&mut executor, account, key, savings, "pull", pull_6.clone(), RADIX_TOKEN).result.is_err());
+    assert!(call_with_badge(&mut executor, account, key, savings, "pull", pull_6.clone(), spender).result.is_ok());
+    assert!(call_with_badge(&mut executor, account, key, savings, "pull", pull_6.clone(), spender).result.is_err());
+
+    // A per-epoch allowance is spendable again in the next epoch.
+    executor.ledger_mut().set_epoch(1);
+    assert!(call_with_badge(&mut executor, account, key, savings, "pull", pull_6.clone(), spender).result.is_ok());
+
+    executor.ledger_mut().set_epoch(2);
+    let revoke = vec![scrypto_encode(&spender), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "revoke", revoke, vec![key]).result.is_ok());
+    assert!(call_with_badge(&mut executor, account, key, savings, "pull", pull_6, spender).result.is_err());
+}
+
+#[test]
+fn test_scheduled_payments_are_paid_once_per_interval() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+    let recipient = new_savings_account(&mut executor, account, key, package, bank);
+
+    let payment = vec![
+        scrypto_encode(&recipient),
+        scrypto_encode(&RADIX_TOKEN),
+        scrypto_encode(&dec!(10)),
+        scrypto_encode(&5u64),
+        scrypto_encode(&0u64),
+        scrypto_encode(&Some(10u64)),
+    ];
+    assert!(call_signed(&mut executor, account, savings, "schedule_payment", payment, vec![key]).result.is_ok());
+
+    // Executing twice in one epoch pays once.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "execute_due_payments", vec![], None)
+        .call_method(savings, "execute_payment", vec!["0".to_owned()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    println!("{:?}\n", receipt1);
+    assert!(receipt1.result.is_ok());
+    let withdraw_111 = vec![scrypto_encode(&dec!(111)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, recipient, "withdraw", withdraw_111, vec![key]).result.is_err());
+
+    // Missed intervals are skipped, and the payment is dropped once it ends.
+    executor.ledger_mut().set_epoch(12);
+    let transaction2 = TransactionBuilder::new(&executor)
+        .call_method(savings, "execute_due_payments", vec![], None)
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction2).unwrap().result.is_ok());
+    let transaction3 = TransactionBuilder::new(&executor)
+        .call_method(savings, "execute_payment", vec!["0".to_owned()], None)
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction3).unwrap().result.is_err());
+    let withdraw_120 = vec![scrypto_encode(&dec!(120)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, recipient, "withdraw", withdraw_120, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_goals_lock_funds_until_reached() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let goal = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!(30)), scrypto_encode(&Some(100u64)), scrypto_encode(&Some(dec!(150)))];
+    assert!(call_signed(&mut executor, account, savings, "create_goal", goal, vec![key]).result.is_ok());
+    let withdraw_80 = vec![scrypto_encode(&dec!(80)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_80, vec![key]).result.is_err());
+
+    // Reaching the target only unlocks withdrawals that leave the balance at the target.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "deposit", vec![format!("60,{}", RADIX_TOKEN)], Some(account))
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction1).unwrap().result.is_ok());
+    let withdraw_140 = vec![scrypto_encode(&dec!(140)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_140, vec![key]).result.is_err());
+    let withdraw_10 = vec![scrypto_encode(&dec!(10)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_10, vec![key]).result.is_ok());
+    let withdraw_121 = vec![scrypto_encode(&dec!(121)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_121, vec![key]).result.is_err());
+    let withdraw_120 = vec![scrypto_encode(&dec!(120)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_120, vec![key]).result.is_ok());
+
+    // The goal unlocks for good at its epoch.
+    let withdraw_30 = vec![scrypto_encode(&dec!(30)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_30.clone(), vec![key]).result.is_err());
+    executor.ledger_mut().set_epoch(100);
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_30, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_inheritance_claim_after_inactivity() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+    let first = new_badge(&mut executor, account, key);
+    let second = new_badge(&mut executor, account, key);
+
+    let beneficiaries = vec![scrypto_encode(&vec![(first, 1u64), (second, 1u64)]), scrypto_encode(&Some(10u64))];
+    assert!(call_signed(&mut executor, account, savings, "set_beneficiaries", beneficiaries, vec![key]).result.is_ok());
+
+    // New beneficiaries only take effect after the limit increase delay.
+    let claim = |executor: &mut TransactionExecutor<InMemorySubstateStore>, beneficiary: Address| {
+        let transaction = TransactionBuilder::new(&*executor)
+            .call_method(savings, "claim_inheritance", vec![format!("1,{}", beneficiary), "0".to_owned(), "10".to_owned()], Some(account))
+            .call_method_with_all_resources(account, "deposit_batch")
+            .build(vec![key])
+            .unwrap();
+        let receipt = executor.run(transaction).unwrap();
+        println!("{:?}\n", receipt);
+        receipt.result.is_ok()
+    };
+    executor.ledger_mut().set_epoch(20);
+    assert!(!claim(&mut executor, first));
+    executor.ledger_mut().set_epoch(34);
+    assert!(claim(&mut executor, first));
+
+    // The owner coming back starts a new round, so the first beneficiary gets half of the new balance.
+    executor.ledger_mut().set_epoch(35);
+    assert!(call_signed(&mut executor, account, savings, "check_in", vec![], vec![key]).result.is_ok());
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "deposit", vec![format!("50,{}", RADIX_TOKEN)], Some(account))
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction1).unwrap().result.is_ok());
+    executor.ledger_mut().set_epoch(45);
+    assert!(claim(&mut executor, first));
+
+    let withdraw_60 = vec![scrypto_encode(&dec!(60)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_60, vec![key]).result.is_err());
+    let withdraw_50 = vec![scrypto_encode(&dec!(50)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_50, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_goals_and_envelopes_lock_funds() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let envelope = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"rent".to_owned()), scrypto_encode(&false)];
+    assert!(call_signed(&mut executor, account, savings, "create_envelope", envelope, vec![key]).result.is_ok());
+    let allocation = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"rent".to_owned()), scrypto_encode(&dec!(60))];
+    assert!(call_signed(&mut executor, account, savings, "allocate", allocation, vec![key]).result.is_ok());
+
+    // Goals can only lock what is outside envelopes.
+    let goal_50 = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!(50)), scrypto_encode(&Some(100u64)), scrypto_encode(&None::<Decimal>)];
+    assert!(call_signed(&mut executor, account, savings, "create_goal", goal_50, vec![key]).result.is_err());
+    let goal_30 = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!(30)), scrypto_encode(&Some(100u64)), scrypto_encode(&None::<Decimal>)];
+    assert!(call_signed(&mut executor, account, savings, "create_goal", goal_30, vec![key]).result.is_ok());
+
+    // 60 is in the envelope and 30 locked, so only 10 can be withdrawn directly.
+    let withdraw_20 = vec![scrypto_encode(&dec!(20)), scrypto_encode(&RADIX_TOKEN)];
+    let withdraw_10 = vec![scrypto_encode(&dec!(10)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_20, vec![key]).result.is_err());
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_10, vec![key]).result.is_ok());
+
+    // An envelope with a goal of its own stays locked, the others follow their own limits.
+    let trip = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"trip".to_owned()), scrypto_encode(&true)];
+    assert!(call_signed(&mut executor, account, savings, "create_envelope", trip, vec![key]).result.is_ok());
+    let transfer = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"rent".to_owned()), scrypto_encode(&"trip".to_owned()), scrypto_encode(&dec!(20))];
+    assert!(call_signed(&mut executor, account, savings, "transfer_between_envelopes", transfer, vec![key]).result.is_ok());
+    let trip_goal = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"trip".to_owned()), scrypto_encode(&None::<u64>), scrypto_encode(&Some(dec!(50)))];
+    assert!(call_signed(&mut executor, account, savings, "set_envelope_goal", trip_goal, vec![key]).result.is_ok());
+    let from_trip = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"trip".to_owned()), scrypto_encode(&dec!(20))];
+    assert!(call_signed(&mut executor, account, savings, "withdraw_from_envelope", from_trip, vec![key]).result.is_err());
+
+    let rent_limit = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"rent".to_owned()), scrypto_encode(&Some(dec!(30)))];
+    assert!(call_signed(&mut executor, account, savings, "set_envelope_limit", rent_limit, vec![key]).result.is_ok());
+    let from_rent_40 = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"rent".to_owned()), scrypto_encode(&dec!(40))];
+    assert!(call_signed(&mut executor, account, savings, "withdraw_from_envelope", from_rent_40, vec![key]).result.is_err());
+    let from_rent_30 = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&"rent".to_owned()), scrypto_encode(&dec!(30))];
+    assert!(call_signed(&mut executor, account, savings, "withdraw_from_envelope", from_rent_30, vec![key]).result.is_ok());
+
+    // The goal unlocks at its epoch.
+    let withdraw_30 = vec![scrypto_encode(&dec!(30)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_30.clone(), vec![key]).result.is_err());
+    executor.ledger_mut().set_epoch(100);
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_30, vec![key]).result.is_ok());
+}
+
+#[test]
+fn test_history_pages_and_minimum() {
+    // Set up environment.
+    let mut ledger = InMemorySubstateStore::with_bootstrap();
+    let mut executor = TransactionExecutor::new(&mut ledger, false);
+    let key = executor.new_public_key();
+    let account = executor.new_account(key);
+    let package = executor
+        .publish_package(include_code!("growth_account"))
+        .unwrap();
+    let bank = new_bank(&mut executor, account, key, package);
+    let savings = new_savings_account(&mut executor, account, key, package, bank);
+
+    let negative = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!("-1"))];
+    assert!(call_signed(&mut executor, account, savings, "set_history_minimum", negative, vec![key]).result.is_err());
+    let minimum = vec![scrypto_encode(&RADIX_TOKEN), scrypto_encode(&dec!(5))];
+    assert!(call_signed(&mut executor, account, savings, "set_history_minimum", minimum, vec![key]).result.is_ok());
+
+    let withdraw_10 = vec![scrypto_encode(&dec!(10)), scrypto_encode(&RADIX_TOKEN)];
+    assert!(call_signed(&mut executor, account, savings, "withdraw", withdraw_10, vec![key]).result.is_ok());
+    assert!(call_signed(&mut executor, account, savings, "bank_token", vec![scrypto_encode(&RADIX_TOKEN)], vec![key]).result.is_ok());
+
+    // Pages past the end, or reaching past it, come back short instead of failing.
+    let transaction1 = TransactionBuilder::new(&executor)
+        .call_method(savings, "deposit", vec![format!("1,{}", RADIX_TOKEN)], Some(account))
+        .call_method(savings, "get_history", vec!["0".to_owned(), "2".to_owned()], None)
+        .call_method(savings, "get_history", vec!["2".to_owned(), u64::MAX.to_string()], None)
+        .call_method(savings, "get_history", vec!["600".to_owned(), "10".to_owned()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    println!("{:?}\n", receipt1);
+    assert!(receipt1.result.is_ok());
+}
+
+// calls a method with a proof of one `badge`, withdrawn from the account, as its last argument and deposits whatever it returns
+fn call_with_badge(
+    executor: &mut TransactionExecutor<InMemorySubstateStore>,
+    account: Address,
+    key: EcdsaPublicKey,
+    component: Address,
+    method: &str,
+    mut args: Vec<Vec<u8>>,
+    badge: Address,
+) -> Receipt {
+    let mut builder = TransactionBuilder::new(&*executor);
+    let (_, _, signer) = builder.add_instruction(Instruction::CloneBucketRef { rid: ECDSA_TOKEN_RID });
+    builder.add_instruction(Instruction::CallMethod {
+        component_address: account,
+        method: "withdraw".to_owned(),
+        args: vec![scrypto_encode(&dec!(1)), scrypto_encode(&badge), scrypto_encode(&BucketRef::from(signer.unwrap()))],
+    });
+    let (_, bid, _) = builder.add_instruction(Instruction::TakeFromWorktop { amount: dec!(1), resource_address: badge });
+    let (_, _, rid) = builder.add_instruction(Instruction::CreateBucketRef { bid: bid.unwrap() });
+    args.push(scrypto_encode(&BucketRef::from(rid.unwrap())));
+    builder.add_instruction(Instruction::CallMethod {
+        component_address: component,
+        method: method.to_owned(),
+        args,
+    });
+    let transaction = builder
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt = executor.run(transaction).unwrap();
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+
+fn new_bank(executor: &mut TransactionExecutor<InMemorySubstateStore>, account: Address, key: EcdsaPublicKey, package: Address) -> Address {
+    let transaction = TransactionBuilder::new(&*executor)
+        .call_function(package, "Bank", "new", vec!["0.09".to_owned(), "5".to_owned()], None)
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt = executor.run(transaction).unwrap();
+    assert!(receipt.result.is_ok());
+    receipt.component(0).unwrap()
+}
+
+
+// a fungible badge held by the account
+fn new_badge(executor: &mut TransactionExecutor<InMemorySubstateStore>, account: Address, key: EcdsaPublicKey) -> Address {
+    let transaction = TransactionBuilder::new(&*executor)
+        .new_badge_fixed(HashMap::new(), 1.into())
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    let receipt = executor.run(transaction).unwrap();
+    assert!(receipt.result.is_ok());
+    receipt.resource_def(0).unwrap()
+}
+
+
+// a single-key account holding 100 XRD
+fn new_savings_account(executor: &mut TransactionExecutor<InMemorySubstateStore>, account: Address, key: EcdsaPublicKey, package: Address, bank: Address) -> Address {
+    let transaction = TransactionBuilder::new(&*executor)
+        .call_function(
+            package,
+            "SavingsAccount",
+            "with_bucket",
+            vec![key.to_string(), format!("100,{}", RADIX_TOKEN), bank.to_string()],
+            Some(account),
+        )
+        .build(vec![key])
+        .unwrap();
+    let receipt = executor.run(transaction).unwrap();
+    assert!(receipt.result.is_ok());
+    receipt.component(0).unwrap()
+}
+
+
+// calls a method with the signers' virtual badge as its last argument and deposits whatever it returns
+fn call_signed(
+    executor: &mut TransactionExecutor<InMemorySubstateStore>,
+    account: Address,
+    component: Address,
+    method: &str,
+    mut args: Vec<Vec<u8>>,
+    signers: Vec<EcdsaPublicKey>,
+) -> Receipt {
+    let mut builder = TransactionBuilder::new(&*executor);
+    let (_, _, rid) = builder.add_instruction(Instruction::CloneBucketRef { rid: ECDSA_TOKEN_RID });
+    args.push(scrypto_encode(&BucketRef::from(rid.unwrap())));
+    builder.add_instruction(Instruction::CallMethod {
+        component_address: component,
+        method: method.to_owned(),
+        args,
+    });
+    let transaction = builder
+        .call_method_with_all_resources(account, "deposit_batch")
+        .build(signers)
+        .unwrap();
+    let receipt = executor.run(transaction).unwrap();
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+// keys of the nfts NftTester mints
+fn nft_keys(serials: &[u64]) -> BTreeSet<NonFungibleKey> {
+    serials.iter().map(|serial| NonFungibleKey::new(serial.to_be_bytes().to_vec())).collect()
+}
+
+// finishes a token's pending bank change, which needs no auth
+fn finish_bank_change(executor: &mut TransactionExecutor<InMemorySubstateStore>, key: EcdsaPublicKey, savings: Address, resource_address: Address) -> Receipt {
+    let transaction = TransactionBuilder::new(&*executor)
+        .call_method(savings, "finish_bank_change", vec![resource_address.to_string()], None)
+        .build(vec![key])
+        .unwrap();
+    let receipt = executor.run(transaction).unwrap();
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+// a multisig account holding 100 XRD
+fn new_multisig(executor: &mut TransactionExecutor<InMemorySubstateStore>, account: Address, package: Address, keys: Vec<EcdsaPublicKey>, quorum: u64, bank: Address) -> Address {
+    let mut builder = TransactionBuilder::new(&*executor);
+    builder.add_instruction(Instruction::CallFunction {
+        package_address: package,
+        blueprint_name: "SavingsAccount".to_owned(),
+        function: "new_multisig".to_owned(),
+        args: vec![
+            scrypto_encode(&keys.iter().map(|key| key.to_string()).collect::<Vec<String>>()),
+            scrypto_encode(&quorum),
+            scrypto_encode(&bank),
+        ],
+    });
+    let transaction1 = builder.build(vec![keys[0]]).unwrap();
+    let receipt1 = executor.run(transaction1).unwrap();
+    assert!(receipt1.result.is_ok());
+    let multisig = receipt1.component(0).unwrap();
+
+    let transaction2 = TransactionBuilder::new(&*executor)
+        .call_method(multisig, "deposit", vec![format!("100,{}", RADIX_TOKEN)], Some(account))
+        .build(vec![keys[0]])
+        .unwrap();
+    assert!(executor.run(transaction2).unwrap().result.is_ok());
+    multisig
+}
+
+// takes nfts out of an NftTester and deposits them into a savings account
+fn give_nfts(executor: &mut TransactionExecutor<InMemorySubstateStore>, key: EcdsaPublicKey, tester: Address, serials: &[u64], savings: Address) {
+    let mut builder = TransactionBuilder::new(&*executor);
+    builder.add_instruction(Instruction::CallMethod {
+        component_address: tester,
+        method: "take".to_owned(),
+        args: vec![scrypto_encode(&nft_keys(serials))],
+    });
+    let transaction = builder
+        .call_method_with_all_resources(savings, "deposit_batch")
+        .build(vec![key])
+        .unwrap();
+    assert!(executor.run(transaction).unwrap().result.is_ok());
+}